@@ -32,6 +32,12 @@ impl Aabb {
         true
     }
 
+    /// The center and radius of a sphere enclosing the box.
+    pub fn bounding_sphere(&self) -> (Vector3<f64>, f64) {
+        let center = (self.minimum + self.maximum) / 2.0;
+        (center, (self.maximum - center).norm())
+    }
+
     pub fn surrounding_box(box0: &Self, box1: &Self) -> Self {
         let small = vector![
             box0.minimum.x.min(box1.minimum.x),
@@ -86,4 +86,12 @@ impl Hittable for AaBox {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(self.box_min, self.box_max))
     }
+
+    fn area(&self) -> f64 {
+        self.sides.area()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        self.sides.sample_surface(time)
+    }
 }
@@ -5,6 +5,7 @@ use nalgebra::vector;
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
+use crate::random::random_range_double;
 
 pub struct XYRect {
     x0: f64,
@@ -41,15 +42,13 @@ impl Hittable for XYRect {
         }
         let u = (x - self.x0) / (self.x1 - self.x0);
         let v = (y - self.y0) / (self.y1 - self.y0);
-        Some(HitRecord::new(
-            r.at(t),
-            vector![0.0, 0.0, 1.0],
-            self.mp.clone(),
-            t,
-            u,
-            v,
-            r,
-        ))
+        Some(
+            HitRecord::new(r.at(t), vector![0.0, 0.0, 1.0], self.mp.clone(), t, u, v, r)
+                .with_tangents(
+                    vector![self.x1 - self.x0, 0.0, 0.0],
+                    vector![0.0, self.y1 - self.y0, 0.0],
+                ),
+        )
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
@@ -58,6 +57,24 @@ impl Hittable for XYRect {
             vector![self.x1, self.y1, self.k + 0.0001],
         ))
     }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        let x = random_range_double(self.x0, self.x1);
+        let y = random_range_double(self.y0, self.y1);
+        let u = (x - self.x0) / (self.x1 - self.x0);
+        let v = (y - self.y0) / (self.y1 - self.y0);
+        Some(HitRecord::on_surface(
+            vector![x, y, self.k],
+            vector![0.0, 0.0, 1.0],
+            self.mp.clone(),
+            u,
+            v,
+        ))
+    }
 }
 
 pub struct XZRect {
@@ -95,15 +112,13 @@ impl Hittable for XZRect {
         }
         let u = (x - self.x0) / (self.x1 - self.x0);
         let v = (z - self.z0) / (self.z1 - self.z0);
-        Some(HitRecord::new(
-            r.at(t),
-            vector![0.0, 1.0, 0.0],
-            self.mp.clone(),
-            t,
-            u,
-            v,
-            r,
-        ))
+        Some(
+            HitRecord::new(r.at(t), vector![0.0, 1.0, 0.0], self.mp.clone(), t, u, v, r)
+                .with_tangents(
+                    vector![self.x1 - self.x0, 0.0, 0.0],
+                    vector![0.0, 0.0, self.z1 - self.z0],
+                ),
+        )
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
@@ -112,6 +127,24 @@ impl Hittable for XZRect {
             vector![self.x1, self.k + 0.0001, self.z1],
         ))
     }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        let x = random_range_double(self.x0, self.x1);
+        let z = random_range_double(self.z0, self.z1);
+        let u = (x - self.x0) / (self.x1 - self.x0);
+        let v = (z - self.z0) / (self.z1 - self.z0);
+        Some(HitRecord::on_surface(
+            vector![x, self.k, z],
+            vector![0.0, 1.0, 0.0],
+            self.mp.clone(),
+            u,
+            v,
+        ))
+    }
 }
 
 pub struct YZRect {
@@ -149,15 +182,13 @@ impl Hittable for YZRect {
         }
         let u = (y - self.y0) / (self.y1 - self.y0);
         let v = (z - self.z0) / (self.z1 - self.z0);
-        Some(HitRecord::new(
-            r.at(t),
-            vector![1.0, 0.0, 0.0],
-            self.mp.clone(),
-            t,
-            u,
-            v,
-            r,
-        ))
+        Some(
+            HitRecord::new(r.at(t), vector![1.0, 0.0, 0.0], self.mp.clone(), t, u, v, r)
+                .with_tangents(
+                    vector![0.0, self.y1 - self.y0, 0.0],
+                    vector![0.0, 0.0, self.z1 - self.z0],
+                ),
+        )
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
@@ -166,4 +197,22 @@ impl Hittable for YZRect {
             vector![self.k + 0.0001, self.y1, self.z1],
         ))
     }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        let y = random_range_double(self.y0, self.y1);
+        let z = random_range_double(self.z0, self.z1);
+        let u = (y - self.y0) / (self.y1 - self.y0);
+        let v = (z - self.z0) / (self.z1 - self.z0);
+        Some(HitRecord::on_surface(
+            vector![self.k, y, z],
+            vector![1.0, 0.0, 0.0],
+            self.mp.clone(),
+            u,
+            v,
+        ))
+    }
 }
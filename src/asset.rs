@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, ImageError};
+
+/// A file a scene depends on that couldn't be loaded.
+#[derive(Debug)]
+pub enum AssetError {
+    Io { path: PathBuf, source: io::Error },
+    Image { path: PathBuf, source: ImageError },
+    Parse { path: PathBuf, reason: String },
+}
+
+impl AssetError {
+    pub fn parse(path: &Path, reason: impl Into<String>) -> Self {
+        AssetError::Parse {
+            path: path.to_path_buf(),
+            reason: reason.into(),
+        }
+    }
+
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        |source| AssetError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    fn image(path: &Path) -> impl FnOnce(ImageError) -> Self + '_ {
+        |source| AssetError::Image {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::Io { path, source } => {
+                write!(f, "couldn't read {}: {source}", path.display())
+            }
+            AssetError::Image { path, source } => {
+                write!(f, "couldn't decode {}: {source}", path.display())
+            }
+            AssetError::Parse { path, reason } => {
+                write!(f, "couldn't parse {}: {reason}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for AssetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AssetError::Io { source, .. } => Some(source),
+            AssetError::Image { source, .. } => Some(source),
+            AssetError::Parse { .. } => None,
+        }
+    }
+}
+
+pub fn read_to_string(path: &Path) -> Result<String, AssetError> {
+    std::fs::read_to_string(path).map_err(AssetError::io(path))
+}
+
+pub fn decode_image(path: &Path) -> Result<DynamicImage, AssetError> {
+    ImageReader::open(path)
+        .map_err(AssetError::io(path))?
+        .decode()
+        .map_err(AssetError::image(path))
+}
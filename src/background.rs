@@ -0,0 +1,12 @@
+use nalgebra::Vector3;
+
+/// The radiance arriving along rays that leave the scene.
+pub trait Background: Send + Sync {
+    fn value(&self, direction: &Vector3<f64>) -> Vector3<f64>;
+}
+
+impl Background for Vector3<f64> {
+    fn value(&self, _direction: &Vector3<f64>) -> Vector3<f64> {
+        *self
+    }
+}
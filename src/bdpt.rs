@@ -0,0 +1,655 @@
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+use std::f64::consts::PI;
+
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::light::Light;
+use crate::random::{random_double, random_int};
+use crate::ray::Ray;
+use crate::spectrum::{narrow_to_wavelength, sample_wavelength, wavelength_weight};
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    point: Vector3<f64>,
+    // Zero for vertices that don't lie on a surface, such as the camera or a medium.
+    normal: Vector3<f64>,
+    rec: Option<HitRecord>,
+    r_in: Option<Ray>,
+    light: Option<usize>,
+    // Set for lights at infinity, including the background seen by escaping camera paths.
+    infinite: bool,
+    beta: Vector3<f64>,
+    delta: bool,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn camera(point: Vector3<f64>, beta: Vector3<f64>) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            point,
+            normal: vector![0.0, 0.0, 0.0],
+            rec: None,
+            r_in: None,
+            light: None,
+            infinite: false,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn light(
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        light: Option<usize>,
+        beta: Vector3<f64>,
+        pdf_fwd: f64,
+    ) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            point,
+            normal,
+            rec: None,
+            r_in: None,
+            light,
+            infinite: false,
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn surface(rec: HitRecord, r_in: Ray, beta: Vector3<f64>) -> Self {
+        let normal = if rec.material().is_volumetric() {
+            vector![0.0, 0.0, 0.0]
+        } else {
+            rec.normal()
+        };
+        Self {
+            kind: VertexKind::Surface,
+            point: rec.point(),
+            normal,
+            rec: Some(rec),
+            r_in: Some(r_in),
+            light: None,
+            infinite: false,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.normal != vector![0.0, 0.0, 0.0]
+    }
+
+    fn is_connectible(&self, lights: &[Arc<dyn Light>]) -> bool {
+        match self.kind {
+            VertexKind::Camera => true,
+            VertexKind::Light => self
+                .light
+                .is_none_or(|index| !lights[index].is_delta_direction()),
+            VertexKind::Surface => {
+                let rec = self.rec.as_ref().unwrap();
+                !rec.material().is_specular(rec)
+            }
+        }
+    }
+
+    /// Converts a solid angle density for sampling `next` from this vertex into an area density.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        if next.infinite {
+            return pdf;
+        }
+        let w = next.point - self.point;
+        let inv_dist_squared = 1.0 / w.norm_squared();
+        let mut pdf = pdf * inv_dist_squared;
+        if next.is_on_surface() {
+            pdf *= next.normal.dot(&w).abs() * inv_dist_squared.sqrt();
+        }
+        pdf
+    }
+
+    fn f(&self, next: &Vertex, time: f64) -> Vector3<f64> {
+        let rec = self.rec.as_ref().unwrap();
+        let scattered = Ray::new(self.point, next.point - self.point, time);
+        rec.material()
+            .eval(self.r_in.as_ref().unwrap(), rec, &scattered)
+    }
+
+    /// The wavelength that dispersion narrowed the subpath down to before reaching this vertex.
+    fn wavelength(&self) -> Option<f64> {
+        self.r_in.and_then(|r| r.wavelength)
+    }
+
+    fn le(&self) -> Vector3<f64> {
+        match (&self.rec, &self.r_in) {
+            (Some(rec), Some(r_in)) => rec.material().emitted(r_in, rec),
+            _ => vector![0.0, 0.0, 0.0],
+        }
+    }
+}
+
+/// A bidirectional path tracer, which connects subpaths traced from the camera with subpaths
+/// traced from the lights and weights each connection strategy by multiple importance sampling.
+pub struct Bdpt<'a> {
+    world: &'a HittableList,
+    lights: &'a [Arc<dyn Light>],
+    camera: &'a Camera,
+    background: &'a dyn Background,
+    max_depth: usize,
+    world_radius: f64,
+}
+
+impl<'a> Bdpt<'a> {
+    pub fn new(
+        world: &'a HittableList,
+        lights: &'a [Arc<dyn Light>],
+        camera: &'a Camera,
+        background: &'a dyn Background,
+        max_depth: usize,
+    ) -> Self {
+        let world_radius = world
+            .bounding_box(0.0, 1.0)
+            .map_or(1.0, |bbox| bbox.bounding_sphere().1);
+        Self {
+            world,
+            lights,
+            camera,
+            background,
+            max_depth,
+            world_radius,
+        }
+    }
+
+    /// Estimates the radiance through pixel `(x, y)`. Light tracing contributions to other pixels
+    /// are splatted into `film`.
+    pub fn sample(&self, x: u32, y: u32, film: &Film) -> Vector3<f64> {
+        let s = ((x as f64) + random_double()) / (film.width() as f64);
+        let t = ((y as f64) + random_double()) / (film.height() as f64);
+        let r = self.camera.get_ray(s, t);
+        // Both subpaths share a wavelength, in case each passes through a dispersive material.
+        let wavelength = sample_wavelength();
+
+        let (camera_path, mut colour) = self.camera_subpath(&r, wavelength);
+        let light_path = self.light_subpath(r.time, wavelength);
+
+        for t in 1..=camera_path.len() {
+            // Light sampling picks its own vertex, so it's still possible when emission failed.
+            for s in 0..=light_path.len().max(1) {
+                let depth = (s + t) as i32 - 2;
+                if (s == 1 && t == 1) || depth < 0 || depth > self.max_depth as i32 {
+                    continue;
+                }
+                colour += self.connect(&light_path, &camera_path, s, t, r.time, film);
+            }
+        }
+
+        colour
+    }
+
+    fn camera_subpath(&self, r: &Ray, wavelength: f64) -> (Vec<Vertex>, Vector3<f64>) {
+        let beta = vector![1.0, 1.0, 1.0];
+        let (_, pdf_dir) = self.camera.pdf_we(r);
+        let mut path = vec![Vertex::camera(r.origin, beta)];
+        let escaped = self.random_walk(
+            *r,
+            beta,
+            pdf_dir,
+            self.max_depth + 1,
+            true,
+            wavelength,
+            &mut path,
+        );
+        (path, escaped)
+    }
+
+    fn light_subpath(&self, time: f64, wavelength: f64) -> Vec<Vertex> {
+        if self.lights.is_empty() {
+            return Vec::new();
+        }
+
+        let index = random_int(0, self.lights.len() as i32 - 1) as usize;
+        let light_pdf = 1.0 / (self.lights.len() as f64);
+        let emission = match self.lights[index].sample_le(time) {
+            Some(emission) if emission.pdf_pos > 0.0 && emission.pdf_dir > 0.0 => emission,
+            _ => return Vec::new(),
+        };
+        if emission.radiance == vector![0.0, 0.0, 0.0] {
+            return Vec::new();
+        }
+
+        let mut path = vec![Vertex::light(
+            emission.ray.origin,
+            emission.normal,
+            Some(index),
+            emission.radiance,
+            emission.pdf_pos * light_pdf,
+        )];
+        let beta = emission.radiance * emission.normal.dot(&emission.ray.direction).abs()
+            / (light_pdf * emission.pdf_pos * emission.pdf_dir);
+        self.random_walk(
+            emission.ray,
+            beta,
+            emission.pdf_dir,
+            self.max_depth,
+            false,
+            wavelength,
+            &mut path,
+        );
+
+        if self.lights[index].is_infinite() || self.lights[index].is_delta_direction() {
+            // Rays from lights at infinity start on a disk, so densities are planar, not angular.
+            if path.len() > 1 {
+                path[1].pdf_fwd = emission.pdf_pos;
+                if path[1].is_on_surface() {
+                    path[1].pdf_fwd *= path[1].normal.dot(&emission.ray.direction).abs();
+                }
+            }
+            path[0].infinite = true;
+            path[0].pdf_fwd = self.infinite_light_density(&-emission.ray.direction);
+        }
+
+        path
+    }
+
+    fn infinite_light_density(&self, direction: &Vector3<f64>) -> f64 {
+        let origin = vector![0.0, 0.0, 0.0];
+        self.lights
+            .iter()
+            .filter(|light| light.is_infinite())
+            .map(|light| light.pdf_li(&origin, direction))
+            .sum::<f64>()
+            / (self.lights.len() as f64)
+    }
+
+    /// Extends `path` by scattering `r` through the scene, returning the background radiance
+    /// picked up if a camera path escapes.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        mut r: Ray,
+        mut beta: Vector3<f64>,
+        pdf: f64,
+        max_vertices: usize,
+        from_camera: bool,
+        wavelength: f64,
+        path: &mut Vec<Vertex>,
+    ) -> Vector3<f64> {
+        let mut pdf_fwd = pdf;
+        r.from_light = !from_camera;
+
+        for bounce in 0..max_vertices {
+            let rec = match self.world.hit(&r, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None if from_camera && self.lights.iter().any(|light| light.is_infinite()) => {
+                    let direction = r.direction.normalize();
+                    let mut vertex = Vertex::light(
+                        r.origin + 2.0 * self.world_radius * direction,
+                        -direction,
+                        None,
+                        beta,
+                        pdf_fwd,
+                    );
+                    vertex.infinite = true;
+                    path.push(vertex);
+                    break;
+                }
+                None if from_camera => {
+                    return beta.component_mul(&self.background.value(&r.direction))
+                }
+                None => break,
+            };
+
+            let mut vertex = Vertex::surface(rec.clone(), r, beta);
+            let prev = path.len() - 1;
+            vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+            if from_camera && vertex.le() != vector![0.0, 0.0, 0.0] {
+                vertex.light = self.lights.iter().position(|light| {
+                    light.hit(&r, rec.t() * (1.0 - 1e-6), rec.t() * (1.0 + 1e-6))
+                });
+            }
+
+            let material = rec.material().clone();
+            if material.is_dispersive() {
+                beta = beta.component_mul(&narrow_to_wavelength(&mut r, wavelength));
+            }
+            let (attenuation, mut scattered) = match material.scatter(&r, &rec) {
+                Some(scatter) if bounce + 1 < max_vertices => scatter,
+                _ => {
+                    path.push(vertex);
+                    break;
+                }
+            };
+            scattered.wavelength = r.wavelength;
+            scattered.from_light = r.from_light;
+
+            let pdf_rev;
+            if material.is_specular(&rec) {
+                vertex.delta = true;
+                pdf_fwd = 0.0;
+                pdf_rev = 0.0;
+            } else {
+                pdf_fwd = material.scattering_pdf(&r, &rec, &scattered);
+                let reversed_in = Ray::new(rec.point(), -scattered.direction, r.time);
+                let reversed_out = Ray::new(rec.point(), -r.direction, r.time);
+                pdf_rev = material.scattering_pdf(&reversed_in, &rec, &reversed_out);
+            }
+            path[prev].pdf_rev = vertex.convert_density(pdf_rev, &path[prev]);
+            path.push(vertex);
+
+            beta = beta.component_mul(&attenuation);
+
+            // Russian roulette, so that long paths through bright scenes stay affordable.
+            if bounce >= 3 {
+                let survival = attenuation.max().min(0.95);
+                if random_double() >= survival {
+                    break;
+                }
+                beta /= survival;
+            }
+
+            r = scattered;
+        }
+
+        vector![0.0, 0.0, 0.0]
+    }
+
+    fn connect(
+        &self,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+        time: f64,
+        film: &Film,
+    ) -> Vector3<f64> {
+        let black = vector![0.0, 0.0, 0.0];
+        let mut sampled = None;
+        let mut splat = None;
+
+        if t > 1 && s != 0 && camera_path[t - 1].kind == VertexKind::Light {
+            return black;
+        }
+
+        let colour = if s == 0 {
+            let pt = &camera_path[t - 1];
+            let emitted = if pt.infinite {
+                self.background
+                    .value(&(pt.point - camera_path[t - 2].point))
+            } else {
+                pt.le()
+            };
+            let colour = pt.beta.component_mul(&emitted);
+            if colour == black || (pt.light.is_none() && !pt.infinite) {
+                // Nothing else could have sampled an emitter that isn't a light.
+                return colour;
+            }
+            colour
+        } else if t == 1 {
+            let qs = &light_path[s - 1];
+            if !qs.is_connectible(self.lights) {
+                return black;
+            }
+            let sample = match self.camera.sample_wi(&qs.point) {
+                Some(sample) if sample.pdf > 0.0 && sample.importance > 0.0 => sample,
+                _ => return black,
+            };
+            let vertex = Vertex::camera(
+                sample.point,
+                vector![1.0, 1.0, 1.0] * (sample.importance / sample.pdf),
+            );
+            let colour = qs
+                .beta
+                .component_mul(&qs.f(&vertex, time))
+                .component_mul(&vertex.beta);
+            if colour == black || !self.unoccluded(qs, &vertex, time) {
+                return black;
+            }
+            splat = Some((
+                (sample.s * (film.width() as f64)) as u32,
+                (sample.t * (film.height() as f64)) as u32,
+            ));
+            sampled = Some(vertex);
+            colour
+        } else if s == 1 {
+            let pt = &camera_path[t - 1];
+            if !pt.is_connectible(self.lights) || self.lights.is_empty() {
+                return black;
+            }
+            let index = random_int(0, self.lights.len() as i32 - 1) as usize;
+            let light_pdf = 1.0 / (self.lights.len() as f64);
+            let sample = match self.lights[index].sample_li(&pt.point, time) {
+                Some(sample) if sample.pdf > 0.0 => sample,
+                _ => return black,
+            };
+            let mut vertex = Vertex::light(
+                sample.point,
+                sample.normal,
+                Some(index),
+                sample.radiance / (sample.pdf * light_pdf),
+                0.0,
+            );
+            vertex.infinite =
+                self.lights[index].is_infinite() || self.lights[index].is_delta_direction();
+            vertex.pdf_fwd = self.pdf_light_origin(&vertex, pt);
+            let colour = pt
+                .beta
+                .component_mul(&pt.f(&vertex, time))
+                .component_mul(&vertex.beta);
+            if colour == black || !self.unoccluded(pt, &vertex, time) {
+                return black;
+            }
+            sampled = Some(vertex);
+            colour
+        } else {
+            let qs = &light_path[s - 1];
+            let pt = &camera_path[t - 1];
+            if !qs.is_connectible(self.lights) || !pt.is_connectible(self.lights) {
+                return black;
+            }
+            let colour = qs
+                .beta
+                .component_mul(&qs.f(pt, time))
+                .component_mul(&pt.f(qs, time))
+                .component_mul(&pt.beta)
+                / (qs.point - pt.point).norm_squared();
+            if colour == black || !self.unoccluded(qs, pt, time) {
+                return black;
+            }
+            match (qs.wavelength(), pt.wavelength()) {
+                // Each subpath's throughput carries the same wavelength's weight, so drop one.
+                (Some(wavelength), Some(_)) => {
+                    colour.zip_map(&wavelength_weight(wavelength), |c, w| {
+                        if w > 0.0 {
+                            c / w
+                        } else {
+                            0.0
+                        }
+                    })
+                }
+                _ => colour,
+            }
+        };
+
+        let colour = colour * self.mis_weight(light_path, camera_path, sampled, s, t, time);
+        match splat {
+            Some((x, y)) => {
+                film.add_splat(x, y, &colour);
+                black
+            }
+            None => colour,
+        }
+    }
+
+    fn unoccluded(&self, a: &Vertex, b: &Vertex, time: f64) -> bool {
+        let direction = b.point - a.point;
+        let distance = direction.norm();
+        let shadow_ray = Ray::new(a.point, direction / distance, time);
+        self.world
+            .hit(&shadow_ray, 0.001, distance - 0.001)
+            .is_none()
+    }
+
+    /// The area density of sampling `next` from `vertex`, having arrived from `prev`.
+    fn pdf(&self, vertex: &Vertex, prev: Option<&Vertex>, next: &Vertex, time: f64) -> f64 {
+        let to_next = Ray::new(vertex.point, next.point - vertex.point, time);
+        let pdf = match vertex.kind {
+            VertexKind::Light => return self.pdf_light(vertex, next),
+            VertexKind::Camera => self.camera.pdf_we(&to_next).1,
+            VertexKind::Surface => {
+                let prev = prev.unwrap();
+                let r_in = Ray::new(prev.point, vertex.point - prev.point, time);
+                vertex
+                    .rec
+                    .as_ref()
+                    .map(|rec| rec.material().scattering_pdf(&r_in, rec, &to_next))
+                    .unwrap()
+            }
+        };
+        vertex.convert_density(pdf, next)
+    }
+
+    /// The area density of emitting towards `next` from the light that `vertex` lies on.
+    fn pdf_light(&self, vertex: &Vertex, next: &Vertex) -> f64 {
+        let w = next.point - vertex.point;
+        if vertex.infinite {
+            let mut pdf = 1.0 / (PI * self.world_radius.powi(2));
+            if next.is_on_surface() {
+                pdf *= next.normal.dot(&w.normalize()).abs();
+            }
+            return pdf;
+        }
+        let light = &self.lights[vertex.light.unwrap()];
+        let (_, pdf_dir) = light.pdf_le(&Ray::new(vertex.point, w, 0.0), &vertex.normal);
+        vertex.convert_density(pdf_dir, next)
+    }
+
+    /// The area density of starting a light path at `vertex`.
+    fn pdf_light_origin(&self, vertex: &Vertex, next: &Vertex) -> f64 {
+        if vertex.infinite {
+            return self.infinite_light_density(&(vertex.point - next.point).normalize());
+        }
+        let w = next.point - vertex.point;
+        let light = &self.lights[vertex.light.unwrap()];
+        let (pdf_pos, _) = light.pdf_le(&Ray::new(vertex.point, w, 0.0), &vertex.normal);
+        pdf_pos / (self.lights.len() as f64)
+    }
+
+    fn mis_weight(
+        &self,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<Vertex>,
+        s: usize,
+        t: usize,
+        time: f64,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let mut light_path = light_path[..s.min(light_path.len())].to_vec();
+        let mut camera_path = camera_path[..t].to_vec();
+        if let Some(vertex) = sampled {
+            if s == 1 {
+                light_path = vec![vertex];
+            } else {
+                camera_path[0] = vertex;
+            }
+        }
+
+        // Update the densities around the connection to reflect this strategy.
+        camera_path[t - 1].delta = false;
+        if s > 0 {
+            light_path[s - 1].delta = false;
+        }
+
+        let pt = &camera_path[t - 1];
+        let pt_minus = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+        let qs = if s > 0 {
+            Some(&light_path[s - 1])
+        } else {
+            None
+        };
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+
+        let pt_pdf_rev = match qs {
+            Some(qs) => self.pdf(qs, qs_minus, pt, time),
+            None => self.pdf_light_origin(pt, pt_minus.unwrap()),
+        };
+        let pt_minus_pdf_rev = pt_minus.map(|pt_minus| match qs {
+            Some(qs) => self.pdf(pt, Some(qs), pt_minus, time),
+            None => self.pdf_light(pt, pt_minus),
+        });
+        let qs_pdf_rev = qs.map(|qs| self.pdf(pt, pt_minus, qs, time));
+        let qs_minus_pdf_rev =
+            qs_minus.map(|qs_minus| self.pdf(qs.unwrap(), Some(pt), qs_minus, time));
+
+        camera_path[t - 1].pdf_rev = pt_pdf_rev;
+        if let Some(pdf) = pt_minus_pdf_rev {
+            camera_path[t - 2].pdf_rev = pdf;
+        }
+        if let Some(pdf) = qs_pdf_rev {
+            light_path[s - 1].pdf_rev = pdf;
+        }
+        if let Some(pdf) = qs_minus_pdf_rev {
+            light_path[s - 2].pdf_rev = pdf;
+        }
+
+        let remap0 = |f: f64| if f != 0.0 { f } else { 1.0 };
+        let mut sum_ri = 0.0;
+
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera_path[i].pdf_rev) / remap0(camera_path[i].pdf_fwd);
+            if !camera_path[i].delta && !camera_path[i - 1].delta {
+                sum_ri += ri;
+            }
+        }
+
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light_path[i].pdf_rev) / remap0(light_path[i].pdf_fwd);
+            let delta_light = if i > 0 {
+                light_path[i - 1].delta
+            } else {
+                light_path[0].light.is_some_and(|index| {
+                    self.lights[index].is_delta_position()
+                        || self.lights[index].is_delta_direction()
+                })
+            };
+            if !light_path[i].delta && !delta_light {
+                sum_ri += ri;
+            }
+        }
+
+        1.0 / (1.0 + sum_ri)
+    }
+}
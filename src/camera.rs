@@ -1,6 +1,15 @@
 use crate::random::{random_in_unit_disk, random_range_double};
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferentials};
 use nalgebra::Vector3;
+use std::f64::consts::PI;
+
+pub struct CameraSample {
+    pub point: Vector3<f64>,
+    pub importance: f64,
+    pub pdf: f64,
+    pub s: f64,
+    pub t: f64,
+}
 
 pub struct Camera {
     origin: Vector3<f64>,
@@ -9,7 +18,10 @@ pub struct Camera {
     vertical: Vector3<f64>,
     u: Vector3<f64>,
     v: Vector3<f64>,
+    w: Vector3<f64>,
     lens_radius: f64,
+    focus_dist: f64,
+    image_plane_area: f64,
     time0: f64,
     time1: f64,
 }
@@ -50,7 +62,10 @@ impl Camera {
             vertical,
             u,
             v,
+            w,
             lens_radius,
+            focus_dist,
+            image_plane_area: viewport_width * viewport_height,
             time0,
             time1,
         }
@@ -62,8 +77,90 @@ impl Camera {
 
         Ray::new(
             self.origin + offset,
-            self.upper_left_corner + s * self.horizontal - t * self.vertical - self.origin - offset,
+            self.direction_through(s, t, &offset),
             random_range_double(self.time0, self.time1),
         )
     }
+
+    /// Like `get_ray`, with differentials for the rays through the lens at the same point that are
+    /// `ds` across and `dt` down the film, so that textures can filter over the area between.
+    pub fn get_ray_differential(&self, s: f64, t: f64, ds: f64, dt: f64) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disk();
+        let offset = self.u * rd.x + self.v * rd.y;
+
+        let mut r = Ray::new(
+            self.origin + offset,
+            self.direction_through(s, t, &offset),
+            random_range_double(self.time0, self.time1),
+        );
+        r.differentials = Some(RayDifferentials {
+            x_origin: r.origin,
+            x_direction: self.direction_through(s + ds, t, &offset).normalize(),
+            y_origin: r.origin,
+            y_direction: self.direction_through(s, t + dt, &offset).normalize(),
+        });
+        r
+    }
+
+    fn direction_through(&self, s: f64, t: f64, offset: &Vector3<f64>) -> Vector3<f64> {
+        self.upper_left_corner + s * self.horizontal - t * self.vertical - self.origin - offset
+    }
+
+    fn lens_area(&self) -> f64 {
+        if self.lens_radius > 0.0 {
+            PI * self.lens_radius.powi(2)
+        } else {
+            1.0
+        }
+    }
+
+    /// The `(s, t)` coordinates that `get_ray` would use to produce `r`, and the cosine between
+    /// `r` and the viewing direction.
+    fn raster(&self, r: &Ray) -> Option<(f64, f64, f64)> {
+        let direction = r.direction.normalize();
+        let cos_theta = -direction.dot(&self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+
+        let focus = r.origin + (self.focus_dist / cos_theta) * direction;
+        let s =
+            (focus - self.upper_left_corner).dot(&self.horizontal) / self.horizontal.norm_squared();
+        let t = (self.upper_left_corner - focus).dot(&self.vertical) / self.vertical.norm_squared();
+        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t) {
+            return None;
+        }
+
+        Some((s, t, cos_theta))
+    }
+
+    /// The area and solid angle pdfs with which `get_ray` would have produced `r`.
+    pub fn pdf_we(&self, r: &Ray) -> (f64, f64) {
+        match self.raster(r) {
+            Some((_, _, cos_theta)) => (
+                1.0 / self.lens_area(),
+                1.0 / (self.image_plane_area * cos_theta.powi(3)),
+            ),
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// Samples a point on the lens that sees `p`, for connecting light paths to the film.
+    pub fn sample_wi(&self, p: &Vector3<f64>) -> Option<CameraSample> {
+        let rd = self.lens_radius * random_in_unit_disk();
+        let point = self.origin + self.u * rd.x + self.v * rd.y;
+
+        let to_point = p - point;
+        let (s, t, cos_theta) = self.raster(&Ray::new(point, to_point, 0.0))?;
+        let importance = 1.0 / (self.image_plane_area * self.lens_area() * cos_theta.powi(4));
+        let pdf = to_point.norm_squared() / (cos_theta * self.lens_area());
+
+        Some(CameraSample {
+            point,
+            importance,
+            pdf,
+            s,
+            t,
+        })
+    }
 }
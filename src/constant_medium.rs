@@ -29,15 +29,8 @@ impl Hittable for ConstantMedium {
         const DEBUG: bool = false;
         let debugging = DEBUG && random_double() < 0.00001;
 
-        let rec1 = match self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY) {
-            Some(rec) => rec,
-            None => return None,
-        };
-
-        let rec2 = match self.boundary.hit(r, rec1.t() + 0.0001, f64::INFINITY) {
-            Some(rec) => rec,
-            None => return None,
-        };
+        let rec1 = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let rec2 = self.boundary.hit(r, rec1.t() + 0.0001, f64::INFINITY)?;
 
         if debugging {
             eprintln!("t_min={}, t_max={}", rec1.t(), rec2.t());
@@ -0,0 +1,100 @@
+/// A piecewise-constant distribution over `[0, 1)`.
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / (n as f64);
+        }
+
+        let func_int = cdf[n];
+        if func_int == 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = (i as f64) / (n as f64);
+            }
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= func_int;
+            }
+        }
+
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn func_int(&self) -> f64 {
+        self.func_int
+    }
+
+    /// Maps `u` to a sample, returning it with its pdf and the index of the piece it fell in.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.count() - 1);
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = if self.func_int > 0.0 {
+            self.func[offset] / self.func_int
+        } else {
+            0.0
+        };
+
+        (((offset as f64) + du) / (self.count() as f64), pdf, offset)
+    }
+}
+
+/// A piecewise-constant distribution over `[0, 1)^2`, given as rows of function values.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| Distribution1D::new(func[v * nu..(v + 1) * nu].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.func_int()).collect());
+
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    pub fn sample_continuous(&self, u0: f64, u1: f64) -> ((f64, f64), f64) {
+        let (d1, pdf1, v) = self.marginal.sample_continuous(u1);
+        let (d0, pdf0, _) = self.conditional[v].sample_continuous(u0);
+        ((d0, d1), pdf0 * pdf1)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * (self.marginal.count() as f64)) as usize).min(self.marginal.count() - 1);
+        let conditional = &self.conditional[row];
+        if self.marginal.func_int() == 0.0 {
+            return 0.0;
+        }
+        let offset = ((u * (conditional.count() as f64)) as usize).min(conditional.count() - 1);
+        conditional.func[offset] / self.marginal.func_int()
+    }
+}
@@ -0,0 +1,192 @@
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+
+use crate::aabb::Aabb;
+use crate::asset::{self, AssetError};
+use crate::background::Background;
+use crate::distribution::Distribution2D;
+use crate::light::{ray_from_infinity, EmissionSample, Light, LightSample};
+use crate::random::random_double;
+use crate::ray::Ray;
+use crate::texture::luminance;
+
+/// Maps a direction to equirectangular coordinates in `[0, 1)^2`, with `y` increasing downwards
+/// from the zenith, along with the sine of its polar angle.
+fn equirectangular_coordinates(direction: &Vector3<f64>, rotation: f64) -> (f64, f64, f64) {
+    let direction = direction.normalize();
+    let theta = (-direction.y).clamp(-1.0, 1.0).acos();
+    let phi = f64::atan2(-direction.z, direction.x) + PI;
+    let x = ((phi - rotation) / (2.0 * PI)).rem_euclid(1.0);
+    let y = 1.0 - theta / PI;
+    (x, y, theta.sin())
+}
+
+fn equirectangular_direction(x: f64, y: f64) -> (Vector3<f64>, f64) {
+    let theta = PI * (1.0 - y);
+    let phi = 2.0 * PI * x;
+    let sin_theta = theta.sin();
+    (
+        vector![-phi.cos() * sin_theta, -theta.cos(), phi.sin() * sin_theta],
+        sin_theta,
+    )
+}
+
+/// An equirectangular high dynamic range image surrounding the scene.
+pub struct EquirectangularImage {
+    pixels: Vec<Vector3<f64>>,
+    width: usize,
+    height: usize,
+    rotation: f64,
+    intensity: f64,
+}
+
+impl EquirectangularImage {
+    /// Loads an `.hdr` or `.exr` image, rotated by `rotation` degrees about the vertical axis and
+    /// scaled by `intensity`.
+    pub fn new(image_path: PathBuf, rotation: f64, intensity: f64) -> Result<Self, AssetError> {
+        let image = asset::decode_image(&image_path)?.into_rgb32f();
+
+        Ok(Self {
+            pixels: image
+                .pixels()
+                .map(|p| vector![p.0[0] as f64, p.0[1] as f64, p.0[2] as f64])
+                .collect(),
+            width: image.width() as usize,
+            height: image.height() as usize,
+            rotation: rotation.to_radians(),
+            intensity,
+        })
+    }
+}
+
+impl Background for EquirectangularImage {
+    fn value(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        let (x, y, _) = equirectangular_coordinates(direction, self.rotation);
+        let i = ((x * (self.width as f64)) as usize).min(self.width - 1);
+        let j = ((y * (self.height as f64)) as usize).min(self.height - 1);
+        self.intensity * self.pixels[j * self.width + i]
+    }
+}
+
+/// Lights the scene from a background surrounding it, importance sampled by luminance using a
+/// piecewise-constant distribution over an equirectangular grid.
+pub struct EnvironmentMap {
+    radiance: Arc<dyn Background>,
+    distribution: Distribution2D,
+    world_center: Vector3<f64>,
+    world_radius: f64,
+}
+
+impl EnvironmentMap {
+    /// Tabulates `radiance` on a `width` by `height` grid. `world_bounds` must enclose the scene, so
+    /// that emitted rays can start outside it.
+    pub fn new(
+        radiance: Arc<dyn Background>,
+        width: usize,
+        height: usize,
+        world_bounds: Aabb,
+    ) -> Self {
+        let mut func: Vec<f64> = (0..width * height)
+            .map(|i| {
+                let x = (((i % width) as f64) + 0.5) / (width as f64);
+                let y = (((i / width) as f64) + 0.5) / (height as f64);
+                let (direction, sin_theta) = equirectangular_direction(x, y);
+                // Weight by sin(theta) to account for the stretching of rows towards the poles.
+                luminance(&radiance.value(&direction)).max(0.0) * sin_theta
+            })
+            .collect();
+
+        // Keep every cell sampleable, since the grid may not line up with features of `radiance`.
+        let floor = 1e-3 * func.iter().sum::<f64>() / (func.len() as f64);
+        for f in func.iter_mut() {
+            *f += floor;
+        }
+
+        let (world_center, world_radius) = world_bounds.bounding_sphere();
+
+        Self {
+            radiance,
+            distribution: Distribution2D::new(&func, width, height),
+            world_center,
+            world_radius,
+        }
+    }
+
+    pub fn from_image(
+        image_path: PathBuf,
+        rotation: f64,
+        intensity: f64,
+        world_bounds: Aabb,
+    ) -> Result<Self, AssetError> {
+        let image = EquirectangularImage::new(image_path, rotation, intensity)?;
+        let (width, height) = (image.width, image.height);
+        Ok(Self::new(Arc::new(image), width, height, world_bounds))
+    }
+
+    fn sample_direction(&self) -> Option<(Vector3<f64>, f64)> {
+        let ((x, y), map_pdf) = self
+            .distribution
+            .sample_continuous(random_double(), random_double());
+        let (direction, sin_theta) = equirectangular_direction(x, y);
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+        Some((direction, map_pdf / (2.0 * PI * PI * sin_theta)))
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        self.radiance.value(direction)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample_li(&self, p: &Vector3<f64>, _time: f64) -> Option<LightSample> {
+        let (direction, pdf) = self.sample_direction()?;
+        Some(LightSample {
+            point: p + 2.0 * self.world_radius * direction,
+            normal: -direction,
+            radiance: self.value(&direction),
+            pdf,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let (x, y, sin_theta) = equirectangular_coordinates(wi, 0.0);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(x, y) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn sample_le(&self, time: f64) -> Option<EmissionSample> {
+        let (direction, pdf_dir) = self.sample_direction()?;
+
+        Some(EmissionSample {
+            ray: ray_from_infinity(&direction, &self.world_center, self.world_radius, time),
+            normal: -direction,
+            radiance: self.value(&direction),
+            pdf_pos: 1.0 / (PI * self.world_radius.powi(2)),
+            pdf_dir,
+        })
+    }
+
+    fn pdf_le(&self, r: &Ray, _normal: &Vector3<f64>) -> (f64, f64) {
+        (
+            1.0 / (PI * self.world_radius.powi(2)),
+            self.pdf_li(&r.origin, &-r.direction),
+        )
+    }
+
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> bool {
+        false
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
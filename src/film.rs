@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use nalgebra::{vector, Vector3};
+
+pub struct Film {
+    width: u32,
+    height: u32,
+    splats: Vec<AtomicU64>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            splats: (0..3 * width * height)
+                .map(|_| AtomicU64::new(0.0f64.to_bits()))
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Adds a contribution to a pixel other than the one currently being sampled.
+    pub fn add_splat(&self, x: u32, y: u32, colour: &Vector3<f64>) {
+        if x >= self.width || y >= self.height || colour.iter().any(|c| !c.is_finite()) {
+            return;
+        }
+        let index = 3 * (y * self.width + x) as usize;
+        for (c, value) in colour.iter().enumerate() {
+            self.splats[index + c]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    Some((f64::from_bits(bits) + value).to_bits())
+                })
+                .unwrap();
+        }
+    }
+
+    pub fn splat(&self, x: u32, y: u32) -> Vector3<f64> {
+        let index = 3 * (y * self.width + x) as usize;
+        let channel = |c: usize| f64::from_bits(self.splats[index + c].load(Ordering::Relaxed));
+        vector![channel(0), channel(1), channel(2)]
+    }
+}
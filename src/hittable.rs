@@ -1,8 +1,10 @@
 use crate::aabb::Aabb;
 use crate::material::Material;
-use crate::ray::Ray;
-use nalgebra::{vector, Vector3};
-use std::sync::Arc;
+use crate::random::hash_point;
+use crate::ray::{Ray, RayDifferentials};
+use crate::texture::Texture;
+use nalgebra::{vector, Vector2, Vector3};
+use std::sync::{Arc, OnceLock};
 
 #[derive(Clone)]
 pub struct HitRecord {
@@ -13,6 +15,17 @@ pub struct HitRecord {
     u: f64,
     v: f64,
     front_face: bool,
+    dpdu: Vector3<f64>,
+    dpdv: Vector3<f64>,
+    dndu: Vector3<f64>,
+    dndv: Vector3<f64>,
+    differentials: Option<RayDifferentials>,
+    // The point and normal before any transforms placed the object in the world.
+    object_point: Vector3<f64>,
+    object_normal: Vector3<f64>,
+    // Worked out from the material the first time it's asked for, which is after the closest hit
+    // has been chosen, so the other candidates never pay for a normal or bump map lookup.
+    shading_normal: OnceLock<Vector3<f64>>,
 }
 
 impl HitRecord {
@@ -26,27 +39,260 @@ impl HitRecord {
         r: &Ray,
     ) -> Self {
         let front_face = r.direction.dot(&normal) < 0.0;
+        let normal = if front_face { normal } else { -normal };
         Self {
             point,
-            normal: if front_face { normal } else { -normal },
+            normal,
             material,
             t,
             u,
             v,
             front_face,
+            dpdu: vector![0.0, 0.0, 0.0],
+            dpdv: vector![0.0, 0.0, 0.0],
+            dndu: vector![0.0, 0.0, 0.0],
+            dndv: vector![0.0, 0.0, 0.0],
+            differentials: r.differentials,
+            object_point: point,
+            object_normal: normal,
+            shading_normal: OnceLock::new(),
         }
     }
 
+    pub fn on_surface(
+        point: Vector3<f64>,
+        outward_normal: Vector3<f64>,
+        material: Arc<dyn Material>,
+        u: f64,
+        v: f64,
+    ) -> Self {
+        Self {
+            point,
+            normal: outward_normal,
+            material,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            dpdu: vector![0.0, 0.0, 0.0],
+            dpdv: vector![0.0, 0.0, 0.0],
+            dndu: vector![0.0, 0.0, 0.0],
+            dndv: vector![0.0, 0.0, 0.0],
+            differentials: None,
+            object_point: point,
+            object_normal: outward_normal,
+            shading_normal: OnceLock::new(),
+        }
+    }
+
+    /// Gives the record the rates of change of its point with `u` and `v`, which let its material
+    /// perturb the shading normal.
+    pub fn with_tangents(mut self, dpdu: Vector3<f64>, dpdv: Vector3<f64>) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    /// Gives the record texture coordinates `u` and `v` in place of the surface's own.
+    pub fn with_uv(mut self, u: f64, v: f64) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    /// Gives the record the rates of change of the outward normal with `u` and `v`, which let
+    /// ray differentials follow specular bounces off curved surfaces.
+    pub fn with_curvature(mut self, dndu: Vector3<f64>, dndv: Vector3<f64>) -> Self {
+        self.dndu = dndu;
+        self.dndv = dndv;
+        self
+    }
+
+    /// The record as it was before any transforms placed the object in the world. Directions
+    /// other than the normal, and the ray's differentials, are dropped.
+    pub fn in_object_space(&self) -> Self {
+        Self {
+            point: self.object_point,
+            normal: self.object_normal,
+            dpdu: vector![0.0, 0.0, 0.0],
+            dpdv: vector![0.0, 0.0, 0.0],
+            dndu: vector![0.0, 0.0, 0.0],
+            dndv: vector![0.0, 0.0, 0.0],
+            differentials: None,
+            shading_normal: OnceLock::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Moves the record's points with `point`, and its directions with `direction`.
+    pub fn transformed(
+        mut self,
+        point: impl Fn(&Vector3<f64>) -> Vector3<f64>,
+        direction: impl Fn(&Vector3<f64>) -> Vector3<f64>,
+    ) -> Self {
+        self.point = point(&self.point);
+        self.normal = direction(&self.normal);
+        self.dpdu = direction(&self.dpdu);
+        self.dpdv = direction(&self.dpdv);
+        self.dndu = direction(&self.dndu);
+        self.dndv = direction(&self.dndv);
+        self.differentials = self.differentials.map(|d| RayDifferentials {
+            x_origin: point(&d.x_origin),
+            x_direction: direction(&d.x_direction),
+            y_origin: point(&d.y_origin),
+            y_direction: direction(&d.y_direction),
+        });
+        if let Some(normal) = self.shading_normal.take() {
+            self.shading_normal = OnceLock::from(direction(&normal));
+        }
+        self
+    }
+
+    /// How far the point moves across the footprint of the ray that found it, along the film's x
+    /// and y, if that ray had differentials.
+    pub fn dpdxy(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let differentials = self.differentials?;
+        // Where each offset ray meets the tangent plane.
+        let offset = |origin: Vector3<f64>, direction: Vector3<f64>| {
+            let cosine = self.normal.dot(&direction);
+            (cosine.abs() > 1e-12).then(|| {
+                let t = self.normal.dot(&(self.point - origin)) / cosine;
+                origin + t * direction - self.point
+            })
+        };
+        Some((
+            offset(differentials.x_origin, differentials.x_direction)?,
+            offset(differentials.y_origin, differentials.y_direction)?,
+        ))
+    }
+
+    /// How u and v change across the footprint of the ray that found the point, along the film's
+    /// x and y, if that ray had differentials and the surface has tangents.
+    pub fn uv_footprint(&self) -> Option<(Vector2<f64>, Vector2<f64>)> {
+        let (dpdx, dpdy) = self.dpdxy()?;
+
+        // Least squares for dp = du * dpdu + dv * dpdv.
+        let (a, b, c) = (
+            self.dpdu.norm_squared(),
+            self.dpdu.dot(&self.dpdv),
+            self.dpdv.norm_squared(),
+        );
+        let determinant = a * c - b * b;
+        if determinant.abs() < 1e-20 {
+            return None;
+        }
+        let solve = |dp: Vector3<f64>| {
+            let (e, f) = (self.dpdu.dot(&dp), self.dpdv.dot(&dp));
+            vector![(c * e - b * f) / determinant, (a * f - b * e) / determinant]
+        };
+        Some((solve(dpdx), solve(dpdy)))
+    }
+
+    /// The differentials of `scattered`, which leaves here by specular reflection or refraction
+    /// of `r_in`, following Igehy's "Tracing Ray Differentials".
+    pub fn scattered_differentials(&self, r_in: &Ray, scattered: &Ray) -> Option<RayDifferentials> {
+        let (dpdx, dpdy) = self.dpdxy()?;
+        let differentials = r_in.differentials?;
+        let normal = self.normal;
+        let wo = -r_in.direction.normalize();
+        let wi = scattered.direction.normalize();
+
+        // The outward normal's derivatives, turned to match `normal`.
+        let sign = if self.front_face { 1.0 } else { -1.0 };
+        let (dndx, dndy) = match self.uv_footprint() {
+            Some((duvdx, duvdy)) => (
+                sign * (self.dndu * duvdx.x + self.dndv * duvdx.y),
+                sign * (self.dndu * duvdy.x + self.dndv * duvdy.y),
+            ),
+            None => (vector![0.0, 0.0, 0.0], vector![0.0, 0.0, 0.0]),
+        };
+
+        let cos_o = wo.dot(&normal);
+        let cos_i = wi.dot(&normal);
+        let direction = |offset_direction: Vector3<f64>, dndx: Vector3<f64>| {
+            let dwodx = -offset_direction - wo;
+            let ddndx = dwodx.dot(&normal) + wo.dot(&dndx);
+            if cos_i > 0.0 {
+                Some(wi - dwodx + 2.0 * (cos_o * dndx + ddndx * normal))
+            } else {
+                // The ratio of refractive indices, from the sines of the two angles.
+                let sin_o = (wo - cos_o * normal).norm();
+                let sin_i = (wi - cos_i * normal).norm();
+                if sin_o < 1e-6 {
+                    return None;
+                }
+                let eta = sin_i / sin_o;
+                let mu = eta * cos_o + cos_i;
+                let dmudx = (eta + eta * eta * cos_o / cos_i) * ddndx;
+                Some(wi - eta * dwodx + mu * dndx + dmudx * normal)
+            }
+        };
+
+        Some(RayDifferentials {
+            x_origin: self.point + dpdx,
+            x_direction: direction(differentials.x_direction, dndx)?.normalize(),
+            y_origin: self.point + dpdy,
+            y_direction: direction(differentials.y_direction, dndy)?.normalize(),
+        })
+    }
+
+    /// Orients a record made by `on_surface` as though `r` had hit it.
+    pub fn seen_along(mut self, r: &Ray) -> Self {
+        self.front_face = r.direction.dot(&self.normal) < 0.0;
+        if !self.front_face {
+            self.normal = -self.normal;
+        }
+        self.shading_normal = OnceLock::new();
+        self
+    }
+
     pub fn point(&self) -> Vector3<f64> {
         self.point
     }
 
+    /// The geometric normal, facing against the ray.
     pub fn normal(&self) -> Vector3<f64> {
         self.normal
     }
 
+    /// The normal for materials to shade with, facing the same way as `normal`. It differs from
+    /// it where a material has a normal or bump map and the surface has tangents.
+    pub fn shading_normal(&self) -> Vector3<f64> {
+        *self.shading_normal.get_or_init(|| {
+            let mut material = &self.material;
+            loop {
+                if let Some(normal) = material.shading_normal(self) {
+                    break normal;
+                }
+                match material.choose(self) {
+                    Some(chosen) => material = chosen,
+                    None => break self.normal,
+                }
+            }
+        })
+    }
+
+    /// Puts `material` in effect here in place of the one that was hit.
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = material;
+        self.shading_normal = OnceLock::new();
+        self
+    }
+
+    /// Counts this record as reached `t` along the ray that was traced, when the point it describes
+    /// was found some other way.
+    pub fn with_t(mut self, t: f64) -> Self {
+        self.t = t;
+        self
+    }
+
+    /// The material in effect at this point, after resolving any that choose between others.
     pub fn material(&self) -> &Arc<dyn Material> {
-        &self.material
+        let mut material = &self.material;
+        while let Some(chosen) = material.choose(self) {
+            material = chosen;
+        }
+        material
     }
 
     pub fn t(&self) -> f64 {
@@ -64,12 +310,29 @@ impl HitRecord {
     pub fn front_face(&self) -> bool {
         self.front_face
     }
+
+    pub fn dpdu(&self) -> Vector3<f64> {
+        self.dpdu
+    }
+
+    pub fn dpdv(&self) -> Vector3<f64> {
+        self.dpdv
+    }
 }
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// Picks a point uniformly by area on the surface, for use as a light sample.
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        None
+    }
 }
 
 pub struct Translate {
@@ -85,18 +348,10 @@ impl Translate {
 
 impl Hittable for Translate {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let moved_r = Ray::new(r.origin - self.offset, r.direction, r.time);
-        self.ptr.hit(&moved_r, t_min, t_max).map(|rec| {
-            HitRecord::new(
-                rec.point() + self.offset,
-                rec.normal(),
-                rec.material().clone(),
-                rec.t(),
-                rec.u(),
-                rec.v(),
-                &moved_r,
-            )
-        })
+        let moved_r = r.transformed(|p| p - self.offset, |d| *d);
+        self.ptr
+            .hit(&moved_r, t_min, t_max)
+            .map(|rec| rec.transformed(|p| p + self.offset, |d| *d))
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
@@ -104,6 +359,48 @@ impl Hittable for Translate {
             .bounding_box(time0, time1)
             .map(|bbox| Aabb::new(bbox.minimum + self.offset, bbox.maximum + self.offset))
     }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        self.ptr
+            .sample_surface(time)
+            .map(|rec| rec.transformed(|p| p + self.offset, |d| *d))
+    }
+}
+
+/// Cuts holes in a surface wherever `opacity` is transparent, so that rays pass straight through,
+/// as for leaves and fences on flat quads. Partly transparent points let a fixed fraction of rays
+/// through.
+pub struct AlphaMasked {
+    ptr: Arc<dyn Hittable>,
+    opacity: Arc<dyn Texture>,
+}
+
+impl AlphaMasked {
+    pub fn new(ptr: Arc<dyn Hittable>, opacity: Arc<dyn Texture>) -> Self {
+        Self { ptr, opacity }
+    }
+}
+
+impl Hittable for AlphaMasked {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let rec = self.ptr.hit(r, t_min, t_max)?;
+            // Hashing the point gives every ray the same answer there, shadow rays included.
+            if hash_point(&rec.point()) < self.opacity.alpha(rec.u(), rec.v(), &rec.point()) {
+                return Some(rec);
+            }
+            t_min = rec.t() + 0.0001;
+        }
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.ptr.bounding_box(time0, time1)
+    }
 }
 
 pub struct RotateY {
@@ -155,46 +452,139 @@ impl RotateY {
             bbox: new_box,
         }
     }
+
+    /// Rotates `v` from the world's space into the object's.
+    fn to_object(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        vector![
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z
+        ]
+    }
+
+    /// Rotates `v` from the object's space back into the world's.
+    fn to_world(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        vector![
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z
+        ]
+    }
 }
 
 impl Hittable for RotateY {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = r.origin;
-        let mut direction = r.direction;
+        let rotated_r = r.transformed(|p| self.to_object(p), |d| self.to_object(d));
+
+        self.ptr
+            .hit(&rotated_r, t_min, t_max)
+            .map(|rec| rec.transformed(|p| self.to_world(p), |d| self.to_world(d)))
+    }
 
-        origin[0] = self.cos_theta * r.origin[0] - self.sin_theta * r.origin[2];
-        origin[2] = self.sin_theta * r.origin[0] + self.cos_theta * r.origin[2];
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        self.bbox
+    }
 
-        direction[0] = self.cos_theta * r.direction[0] - self.sin_theta * r.direction[2];
-        direction[2] = self.sin_theta * r.direction[0] + self.cos_theta * r.direction[2];
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
 
-        let rotated_r = Ray::new(origin, direction, r.time);
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        self.ptr
+            .sample_surface(time)
+            .map(|rec| rec.transformed(|p| self.to_world(p), |d| self.to_world(d)))
+    }
+}
 
-        if let Some(rec) = self.ptr.hit(&rotated_r, t_min, t_max) {
-            let mut point = rec.point();
-            let mut normal = rec.normal();
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::{vector, Vector3};
+
+    use super::{AlphaMasked, Hittable, RotateY, Translate};
+    use crate::aarect::XZRect;
+    use crate::hittable_list::HittableList;
+    use crate::material::Lambertian;
+    use crate::ray::{Ray, RayDifferentials};
+    use crate::texture::SolidColour;
+
+    /// A unit square at height 1 masked by a constant `opacity`, over a floor at height 0.
+    fn masked_over_floor(opacity: f64) -> HittableList {
+        let material = Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]));
+        let panel = XZRect::new(0.0, 1.0, 0.0, 1.0, 1.0, material.clone());
+        let mask = Arc::new(SolidColour::new(vector![1.0, 1.0, 1.0] * opacity));
+        let mut world = HittableList::new(Arc::new(AlphaMasked::new(Arc::new(panel), mask)));
+        world.add(Arc::new(XZRect::new(-1.0, 2.0, -1.0, 2.0, 0.0, material)));
+        world
+    }
 
-            point[0] = self.cos_theta * rec.point()[0] + self.sin_theta * rec.point()[2];
-            point[2] = -self.sin_theta * rec.point()[0] + self.cos_theta * rec.point()[2];
+    /// The height at which a ray straight down through `(x, z)` stops.
+    fn stopped_at(world: &HittableList, x: f64, z: f64) -> f64 {
+        let r = Ray::new(vector![x, 2.0, z], vector![0.0, -1.0, 0.0], 0.0);
+        world.hit(&r, 0.001, f64::INFINITY).unwrap().point().y
+    }
 
-            normal[0] = self.cos_theta * rec.normal()[0] + self.sin_theta * rec.normal()[2];
-            normal[2] = -self.sin_theta * rec.normal()[0] + self.cos_theta * rec.normal()[2];
+    #[test]
+    fn holes_let_rays_through_and_solid_parts_stop_them() {
+        let points: Vec<_> = (0..400)
+            .map(|n| {
+                (
+                    ((n % 20) as f64 + 0.5) / 20.0,
+                    ((n / 20) as f64 + 0.5) / 20.0,
+                )
+            })
+            .collect();
+
+        let clear = masked_over_floor(0.0);
+        let solid = masked_over_floor(1.0);
+        for &(x, z) in &points {
+            assert_eq!(stopped_at(&clear, x, z), 0.0);
+            assert_eq!(stopped_at(&solid, x, z), 1.0);
+        }
 
-            Some(HitRecord::new(
-                point,
-                normal,
-                rec.material().clone(),
-                rec.t(),
-                rec.u(),
-                rec.v(),
-                &rotated_r,
-            ))
-        } else {
-            None
+        // Half opaque stops about half of the rays, and always the same ones.
+        let half = masked_over_floor(0.5);
+        let stopped = points
+            .iter()
+            .filter(|&&(x, z)| stopped_at(&half, x, z) == 1.0)
+            .count();
+        assert!((150..250).contains(&stopped), "{stopped} of 400 stopped");
+        for &(x, z) in &points {
+            assert_eq!(stopped_at(&half, x, z), stopped_at(&half, x, z));
         }
     }
 
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
-        self.bbox
+    #[test]
+    fn footprints_survive_translation_and_rotation() {
+        // Looking straight down at the middle of a unit square, with differentials that spread by
+        // a hundredth of it along x and z on reaching it.
+        let mut r = Ray::new(vector![0.5, 1.0, 0.5], vector![0.0, -1.0, 0.0], 0.0);
+        r.differentials = Some(RayDifferentials {
+            x_origin: r.origin,
+            x_direction: vector![0.01, -1.0, 0.0].normalize(),
+            y_origin: r.origin,
+            y_direction: vector![0.0, -1.0, 0.01].normalize(),
+        });
+        let square = || {
+            let material = Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]));
+            Arc::new(XZRect::new(0.0, 1.0, 0.0, 1.0, 0.0, material))
+        };
+        let assert_footprint = |object: &dyn Hittable, r: &Ray| {
+            let rec = object.hit(r, 0.001, f64::INFINITY).unwrap();
+            let (duvdx, duvdy) = rec.uv_footprint().unwrap();
+            assert!((duvdx - vector![0.01, 0.0]).norm() < 1e-9, "{duvdx}");
+            assert!((duvdy - vector![0.0, 0.01]).norm() < 1e-9, "{duvdy}");
+        };
+
+        assert_footprint(&*square(), &r);
+
+        let offset = vector![5.0, -2.0, 3.0];
+        let translated = Translate::new(square(), offset);
+        assert_footprint(&translated, &r.transformed(|p| p + offset, |d| *d));
+
+        let rotated = RotateY::new(square(), 30.0);
+        let turn = |v: &Vector3<f64>| rotated.to_world(v);
+        assert_footprint(&rotated, &r.transformed(turn, turn));
     }
 }
@@ -1,5 +1,6 @@
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
+use crate::random::random_double;
 use crate::ray::Ray;
 use std::sync::Arc;
 
@@ -51,9 +52,9 @@ impl Hittable for HittableList {
             if let Some(bbox) = object.bounding_box(time0, time1) {
                 if first_box {
                     temp_box = bbox;
+                    first_box = false;
                 } else {
                     temp_box = Aabb::surrounding_box(&temp_box, &bbox);
-                    first_box = false;
                 }
             } else {
                 return None;
@@ -62,4 +63,20 @@ impl Hittable for HittableList {
 
         Some(temp_box)
     }
+
+    fn area(&self) -> f64 {
+        self.objects.iter().map(|object| object.area()).sum()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<HitRecord> {
+        let mut target = random_double() * self.area();
+        for object in &self.objects {
+            let area = object.area();
+            if target < area {
+                return object.sample_surface(time);
+            }
+            target -= area;
+        }
+        None
+    }
 }
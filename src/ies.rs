@@ -0,0 +1,314 @@
+use std::path::PathBuf;
+
+use nalgebra::{vector, Vector3};
+
+use crate::asset::{self, AssetError};
+
+/// A luminaire's measured intensity distribution, read from an IESNA LM-63 photometric file and
+/// normalised so that its brightest direction has a value of one.
+pub struct IesProfile {
+    vertical_angles: Vec<f64>,
+    horizontal_angles: Vec<f64>,
+    // Indexed by horizontal angle, then vertical angle.
+    candela: Vec<Vec<f64>>,
+}
+
+impl IesProfile {
+    /// Loads a type C photometric file, where vertical angles are measured from the nadir and
+    /// horizontal angles around it.
+    pub fn new(path: PathBuf) -> Result<Self, AssetError> {
+        let contents = asset::read_to_string(&path)?;
+        let mut lines = contents.lines();
+
+        // Skip the keyword header up to the tilt specification.
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find(|line| line.starts_with("TILT="))
+            .ok_or_else(|| AssetError::parse(&path, "no TILT= line"))?;
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f64>()
+                    .map_err(|_| AssetError::parse(&path, format!("{token:?} isn't a number")))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(AssetError::parse(&path, "the file ends early")))
+        };
+
+        if tilt == "TILT=INCLUDE" {
+            let _geometry = next()?;
+            let count = next()? as usize;
+            for _ in 0..2 * count {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let _multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        if photometric_type != 1.0 {
+            return Err(AssetError::parse(
+                &path,
+                "only type C photometry is supported",
+            ));
+        }
+        let _units = next()?;
+        let _dimensions = (next()?, next()?, next()?);
+        let _factors = (next()?, next()?, next()?);
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut candela = (0..horizontal_count)
+            .map(|_| (0..vertical_count).map(|_| next()).collect())
+            .collect::<Result<Vec<Vec<_>>, _>>()?;
+
+        let max = candela.iter().flatten().cloned().fold(0.0, f64::max);
+        if max > 0.0 {
+            for value in candela.iter_mut().flatten() {
+                *value /= max;
+            }
+        }
+
+        Ok(Self {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    /// The relative intensity towards `direction` from a luminaire pointing along `nadir`. The
+    /// zero horizontal angle lies towards `+x`, or `+y` if the nadir is close to the x axis.
+    pub fn value(&self, direction: &Vector3<f64>, nadir: &Vector3<f64>) -> f64 {
+        let direction = direction.normalize();
+        let nadir = nadir.normalize();
+
+        let a = if nadir.x.abs() > 0.9 {
+            vector![0.0, 1.0, 0.0]
+        } else {
+            vector![1.0, 0.0, 0.0]
+        };
+        let c0 = (a - a.dot(&nadir) * nadir).normalize();
+        let c90 = nadir.cross(&c0);
+
+        let vertical = direction.dot(&nadir).clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = direction
+            .dot(&c90)
+            .atan2(direction.dot(&c0))
+            .to_degrees()
+            .rem_euclid(360.0);
+
+        self.interpolate(self.fold_horizontal(horizontal), vertical)
+    }
+
+    /// Maps a horizontal angle into the range covered by the file, using the symmetry that the
+    /// range implies: one angle for rotational symmetry, 0 to 90 for symmetry in each quadrant,
+    /// 0 to 180 or 90 to 270 for bilateral symmetry, and anything else for a full circle.
+    fn fold_horizontal(&self, angle: f64) -> f64 {
+        let first = self.horizontal_angles[0];
+        let last = *self.horizontal_angles.last().unwrap();
+        if self.horizontal_angles.len() == 1 {
+            first
+        } else if first == 0.0 && last == 90.0 {
+            let angle = if angle > 180.0 { 360.0 - angle } else { angle };
+            if angle > 90.0 {
+                180.0 - angle
+            } else {
+                angle
+            }
+        } else if first == 0.0 && last == 180.0 {
+            if angle > 180.0 {
+                360.0 - angle
+            } else {
+                angle
+            }
+        } else if first == 90.0 && last == 270.0 {
+            if (90.0..=270.0).contains(&angle) {
+                angle
+            } else {
+                (180.0 - angle).rem_euclid(360.0)
+            }
+        } else {
+            first + (angle - first).rem_euclid(360.0)
+        }
+    }
+
+    fn interpolate(&self, horizontal: f64, vertical: f64) -> f64 {
+        let (v0, v1, tv) = match bracket(&self.vertical_angles, vertical) {
+            Some(bracket) => bracket,
+            None => return 0.0,
+        };
+        let (h0, h1, th) = self.horizontal_bracket(horizontal);
+
+        let row = |h: usize| (1.0 - tv) * self.candela[h][v0] + tv * self.candela[h][v1];
+        (1.0 - th) * row(h0) + th * row(h1)
+    }
+
+    /// Like `bracket` for a folded horizontal angle. A full circle that stops short of 360
+    /// degrees wraps around from its last angle to its first.
+    fn horizontal_bracket(&self, angle: f64) -> (usize, usize, f64) {
+        if let Some(bracket) = bracket(&self.horizontal_angles, angle) {
+            return bracket;
+        }
+        let last = self.horizontal_angles.len() - 1;
+        let width = self.horizontal_angles[0] + 360.0 - self.horizontal_angles[last];
+        if width > 0.0 {
+            (
+                last,
+                0,
+                ((angle - self.horizontal_angles[last]) / width).clamp(0.0, 1.0),
+            )
+        } else {
+            (last, last, 0.0)
+        }
+    }
+}
+
+/// Finds the pair of entries in the sorted `angles` surrounding `angle`, with the fraction of the
+/// way from the first to the second. Returns `None` outside of their range.
+fn bracket(angles: &[f64], angle: f64) -> Option<(usize, usize, f64)> {
+    let first = *angles.first()?;
+    let last = *angles.last()?;
+    if angle < first || angle > last {
+        return None;
+    }
+    if angles.len() == 1 {
+        return Some((0, 0, 0.0));
+    }
+
+    let i = angles
+        .partition_point(|&a| a <= angle)
+        .saturating_sub(1)
+        .min(angles.len() - 2);
+    let width = angles[i + 1] - angles[i];
+    let t = if width > 0.0 {
+        (angle - angles[i]) / width
+    } else {
+        0.0
+    };
+    Some((i, i + 1, t.clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use nalgebra::{vector, Vector2, Vector3};
+
+    use super::IesProfile;
+    use crate::aarect::XZRect;
+    use crate::hittable_list::HittableList;
+    use crate::light::{Light, PointLight};
+    use crate::material::Lambertian;
+    use crate::ray::Ray;
+
+    // The candela values along the 0 and 90 degree planes of downlight.ies, every 2.5 degrees.
+    fn candela(horizontal: f64, vertical: f64) -> f64 {
+        let row = if horizontal == 0.0 {
+            [
+                1000.0, 990.5, 962.6, 917.7, 858.1, 786.7, 707.0, 622.6, 536.9, 453.1, 374.2,
+                302.8, 241.8, 195.1, 167.5, 161.1, 171.1, 183.5, 181.3, 155.9, 113.5, 69.3, 35.3,
+                15.1, 5.4, 1.7, 0.5, 0.1, 0.0,
+            ]
+        } else {
+            [
+                1000.0, 990.5, 962.6, 917.7, 858.1, 786.7, 707.0, 622.6, 536.9, 453.2, 374.5,
+                304.0, 246.2, 208.2, 198.9, 223.4, 272.6, 319.5, 331.2, 291.9, 215.0, 131.6, 66.7,
+                28.1, 9.9, 2.9, 0.8, 0.2, 0.0,
+            ]
+        };
+        row[(vertical / 2.5).round() as usize]
+    }
+
+    /// The radiance seen looking straight down at a white floor at `(x, 0, z)`, lit only by the
+    /// profile hanging 2 units above the origin.
+    fn floor_radiance(x: f64, z: f64) -> f64 {
+        let profile = Arc::new(IesProfile::new(PathBuf::from("downlight.ies")).unwrap());
+        let floor = Arc::new(XZRect::new(
+            -100.0,
+            100.0,
+            -100.0,
+            100.0,
+            0.0,
+            Arc::new(Lambertian::new(vector![1.0, 1.0, 1.0])),
+        ));
+        let world = HittableList::new(floor);
+        let lights: Vec<Arc<dyn Light>> = vec![Arc::new(
+            PointLight::new(vector![0.0, 2.0, 0.0], vector![1.0, 1.0, 1.0]).with_profile(profile),
+        )];
+
+        let r = Ray::new(vector![x, 1.0, z], vector![0.0, -1.0, 0.0], 0.0);
+        let background = Vector3::zeros();
+        crate::ray_colour(&r, None, &background, &world, &lights, 1).x
+    }
+
+    #[test]
+    fn floor_irradiance_follows_profile() {
+        for vertical in [0.0, 10.0, 22.5, 30.0, 45.0, 60.0] {
+            for (horizontal, direction) in [(0.0, vector![1.0, 0.0]), (90.0, vector![0.0, 1.0])] {
+                let offset: Vector2<f64> = 2.0 * f64::tan(f64::to_radians(vertical)) * direction;
+                let cosine = f64::cos(f64::to_radians(vertical));
+                // A white Lambertian floor reflects irradiance I cos / d^2 as radiance E / pi.
+                let expected = candela(horizontal, vertical) / 1000.0 * cosine.powi(3) / (4.0 * PI);
+                let radiance = floor_radiance(offset.x, offset.y);
+                assert!(
+                    (radiance - expected).abs() < 1e-3 * expected.max(1e-3),
+                    "at {horizontal} and {vertical} degrees, {radiance} isn't {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn floor_is_brightest_under_the_nadir_and_dark_past_the_cutoff() {
+        let nadir = floor_radiance(0.0, 0.0);
+        for x in [0.25, 0.5, 1.0, 2.0, 3.0] {
+            assert!(floor_radiance(x, 0.0) < nadir);
+            assert!(floor_radiance(0.0, x) < nadir);
+        }
+        // Beyond 75 degrees, where the profile has all but cut off.
+        for x in [7.5, 10.0, 20.0] {
+            assert!(floor_radiance(x, 0.0) < 1e-4 * nadir);
+            assert!(floor_radiance(0.0, x) < 1e-3 * nadir);
+        }
+    }
+
+    #[test]
+    fn horizontal_angles_fold_by_their_symmetry() {
+        let profile = |horizontal_angles: Vec<f64>| IesProfile {
+            vertical_angles: vec![0.0, 90.0],
+            candela: vec![vec![1.0, 1.0]; horizontal_angles.len()],
+            horizontal_angles,
+        };
+
+        let quadrant = profile(vec![0.0, 45.0, 90.0]);
+        assert_eq!(quadrant.fold_horizontal(135.0), 45.0);
+        assert_eq!(quadrant.fold_horizontal(300.0), 60.0);
+
+        let bilateral = profile(vec![0.0, 90.0, 180.0]);
+        assert_eq!(bilateral.fold_horizontal(270.0), 90.0);
+
+        let lateral = profile(vec![90.0, 180.0, 270.0]);
+        assert_eq!(lateral.fold_horizontal(0.0), 180.0);
+        assert_eq!(lateral.fold_horizontal(45.0), 135.0);
+        assert_eq!(lateral.fold_horizontal(315.0), 225.0);
+        assert_eq!(lateral.fold_horizontal(200.0), 200.0);
+
+        let full = profile(vec![0.0, 120.0, 240.0]);
+        assert_eq!(full.fold_horizontal(300.0), 300.0);
+        assert_eq!(full.horizontal_bracket(300.0), (2, 0, 0.5));
+    }
+}
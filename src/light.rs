@@ -0,0 +1,528 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::ies::IesProfile;
+use crate::random::{random_double, random_in_cone, random_in_unit_disk, random_unit_vector};
+use crate::ray::Ray;
+
+pub struct LightSample {
+    pub point: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub radiance: Vector3<f64>,
+    pub pdf: f64,
+}
+
+pub struct EmissionSample {
+    pub ray: Ray,
+    pub normal: Vector3<f64>,
+    pub radiance: Vector3<f64>,
+    pub pdf_pos: f64,
+    pub pdf_dir: f64,
+}
+
+/// Starts a ray travelling against `direction` on a disk just outside the sphere bounding the
+/// scene, as emitted by a light at infinity.
+pub fn ray_from_infinity(
+    direction: &Vector3<f64>,
+    world_center: &Vector3<f64>,
+    world_radius: f64,
+    time: f64,
+) -> Ray {
+    let a = if direction.x.abs() > 0.9 {
+        vector![0.0, 1.0, 0.0]
+    } else {
+        vector![1.0, 0.0, 0.0]
+    };
+    let v1 = direction.cross(&a).normalize();
+    let v2 = direction.cross(&v1);
+    let disk = random_in_unit_disk();
+    let origin = world_center + world_radius * (direction + disk.x * v1 + disk.y * v2);
+    Ray::new(origin, -direction, time)
+}
+
+pub trait Light: Send + Sync {
+    /// Samples a point on the light as seen from `p`, with a solid angle pdf.
+    fn sample_li(&self, p: &Vector3<f64>, time: f64) -> Option<LightSample>;
+
+    /// The solid angle pdf with which `sample_li` would pick the direction `wi` from `p`.
+    fn pdf_li(&self, p: &Vector3<f64>, wi: &Vector3<f64>) -> f64;
+
+    /// Samples a ray leaving the light, with separate area and solid angle pdfs.
+    fn sample_le(&self, time: f64) -> Option<EmissionSample>;
+
+    /// The area and solid angle pdfs with which `sample_le` would have picked `r`.
+    fn pdf_le(&self, r: &Ray, normal: &Vector3<f64>) -> (f64, f64);
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool;
+
+    /// Whether the light surrounds the scene at infinity, like an environment map.
+    fn is_infinite(&self) -> bool {
+        false
+    }
+
+    /// Whether the light emits from a single point, so can't be hit by rays.
+    fn is_delta_position(&self) -> bool {
+        false
+    }
+
+    /// Whether the light arrives from a single direction, so can't be hit by rays.
+    fn is_delta_direction(&self) -> bool {
+        false
+    }
+}
+
+/// What an `AreaLight` scales the emission of its surface to.
+#[derive(Clone, Copy)]
+pub enum Emission {
+    /// An average luminance over the surface.
+    Radiance(f64),
+    /// A total power in watts, over the surface and every side that emits.
+    Power(f64),
+}
+
+/// Why an `AreaLight` couldn't scale the emission of its surface.
+#[derive(Debug, PartialEq)]
+pub enum FitError {
+    /// The shape can't pick points on its surface, so its area and material aren't known.
+    NoSurface,
+    /// The shape's material doesn't emit light that can be scaled.
+    NotEmissive,
+    /// The material was already fitted to another shape, and can only shine one way.
+    AlreadyFitted,
+}
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FitError::NoSurface => write!(f, "the light's shape has no surface to sample"),
+            FitError::NotEmissive => write!(f, "the light's material can't be scaled"),
+            FitError::AlreadyFitted => {
+                write!(
+                    f,
+                    "the light's material was already fitted to another shape"
+                )
+            }
+        }
+    }
+}
+
+impl Error for FitError {}
+
+pub struct AreaLight {
+    shape: Arc<dyn Hittable>,
+}
+
+impl AreaLight {
+    /// Lights the scene from `shape`, whose surface should share one emissive material.
+    pub fn new(shape: Arc<dyn Hittable>) -> Self {
+        Self { shape }
+    }
+
+    /// Scales the emission of the shape's material so that its average luminance over the
+    /// surface is `radiance`.
+    pub fn with_radiance(self, radiance: f64) -> Result<Self, FitError> {
+        self.fit(Emission::Radiance(radiance))
+    }
+
+    /// Scales the emission of the shape's material so that the surface radiates `watts` in total.
+    pub fn with_power(self, watts: f64) -> Result<Self, FitError> {
+        self.fit(Emission::Power(watts))
+    }
+
+    fn fit(self, emission: Emission) -> Result<Self, FitError> {
+        let rec = self
+            .shape
+            .sample_surface(0.0)
+            .filter(|_| self.shape.area() > 0.0)
+            .ok_or(FitError::NoSurface)?;
+        rec.material().fit_emission(&*self.shape, emission)?;
+        Ok(self)
+    }
+}
+
+impl Light for AreaLight {
+    fn sample_li(&self, p: &Vector3<f64>, time: f64) -> Option<LightSample> {
+        let rec = self.shape.sample_surface(time)?;
+        let to_light = rec.point() - p;
+        let distance_squared = to_light.norm_squared();
+        let cosine = rec.normal().dot(&to_light).abs() / distance_squared.sqrt();
+        if cosine < 1e-7 {
+            return None;
+        }
+
+        let r_in = Ray::new(*p, to_light, time);
+        Some(LightSample {
+            point: rec.point(),
+            normal: rec.normal(),
+            radiance: rec
+                .material()
+                .emitted(&r_in, &rec.clone().seen_along(&r_in)),
+            pdf: distance_squared / (cosine * self.shape.area()),
+        })
+    }
+
+    fn pdf_li(&self, p: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        match self
+            .shape
+            .hit(&Ray::new(*p, *wi, 0.0), 0.001, f64::INFINITY)
+        {
+            Some(rec) => {
+                let distance_squared = (rec.point() - p).norm_squared();
+                let cosine = rec.normal().dot(&wi.normalize()).abs();
+                distance_squared / (cosine * self.shape.area())
+            }
+            None => 0.0,
+        }
+    }
+
+    fn sample_le(&self, time: f64) -> Option<EmissionSample> {
+        let rec = self.shape.sample_surface(time)?;
+
+        // Emission leaves both faces, so pick one before cosine sampling around it.
+        let normal = if random_double() < 0.5 {
+            rec.normal()
+        } else {
+            -rec.normal()
+        };
+        let mut direction = normal + random_unit_vector();
+        if direction.norm() < 1e-7 {
+            direction = normal;
+        }
+        let direction = direction.normalize();
+        let r_in = Ray::new(rec.point() + direction, -direction, time);
+
+        Some(EmissionSample {
+            ray: Ray::new(rec.point(), direction, time),
+            normal,
+            radiance: rec
+                .material()
+                .emitted(&r_in, &rec.clone().seen_along(&r_in)),
+            pdf_pos: 1.0 / self.shape.area(),
+            pdf_dir: normal.dot(&direction) / (2.0 * PI),
+        })
+    }
+
+    fn pdf_le(&self, r: &Ray, normal: &Vector3<f64>) -> (f64, f64) {
+        let cosine = normal.dot(&r.direction.normalize()).abs();
+        (1.0 / self.shape.area(), cosine / (2.0 * PI))
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.shape.hit(r, t_min, t_max).is_some()
+    }
+}
+
+/// A light emitting `intensity` equally in all directions from a single point.
+pub struct PointLight {
+    position: Vector3<f64>,
+    intensity: Vector3<f64>,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl PointLight {
+    pub fn new(position: Vector3<f64>, intensity: Vector3<f64>) -> Self {
+        Self {
+            position,
+            intensity,
+            profile: None,
+        }
+    }
+
+    /// Shapes the emission by a measured profile, with the luminaire pointing straight down.
+    /// `intensity` then gives the intensity in the profile's brightest direction.
+    pub fn with_profile(mut self, profile: Arc<IesProfile>) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    fn intensity(&self, w: &Vector3<f64>) -> Vector3<f64> {
+        match &self.profile {
+            Some(profile) => self.intensity * profile.value(w, &vector![0.0, -1.0, 0.0]),
+            None => self.intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Vector3<f64>, _time: f64) -> Option<LightSample> {
+        let to_point = p - self.position;
+        Some(LightSample {
+            point: self.position,
+            normal: vector![0.0, 0.0, 0.0],
+            radiance: self.intensity(&to_point) / to_point.norm_squared(),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vector3<f64>, _wi: &Vector3<f64>) -> f64 {
+        0.0
+    }
+
+    fn sample_le(&self, time: f64) -> Option<EmissionSample> {
+        let direction = random_unit_vector();
+        Some(EmissionSample {
+            ray: Ray::new(self.position, direction, time),
+            normal: direction,
+            radiance: self.intensity(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn pdf_le(&self, _r: &Ray, _normal: &Vector3<f64>) -> (f64, f64) {
+        (0.0, 1.0 / (4.0 * PI))
+    }
+
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> bool {
+        false
+    }
+
+    fn is_delta_position(&self) -> bool {
+        true
+    }
+}
+
+/// A point light restricted to a cone, fading out smoothly between `falloff_start` and `angle`
+/// degrees from its axis.
+pub struct SpotLight {
+    position: Vector3<f64>,
+    direction: Vector3<f64>,
+    intensity: Vector3<f64>,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vector3<f64>,
+        target: Vector3<f64>,
+        intensity: Vector3<f64>,
+        angle: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: (target - position).normalize(),
+            intensity,
+            cos_total_width: angle.to_radians().cos(),
+            cos_falloff_start: falloff_start.min(angle).to_radians().cos(),
+            profile: None,
+        }
+    }
+
+    /// Shapes the emission within the cone by a measured profile, with the luminaire pointing
+    /// along the spot's axis.
+    pub fn with_profile(mut self, profile: Arc<IesProfile>) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    fn falloff(&self, w: &Vector3<f64>) -> f64 {
+        let cos_theta = w.normalize().dot(&self.direction);
+        let falloff = if cos_theta < self.cos_total_width {
+            0.0
+        } else if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else {
+            ((cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width))
+                .powi(4)
+        };
+        match &self.profile {
+            Some(profile) => falloff * profile.value(w, &self.direction),
+            None => falloff,
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Vector3<f64>, _time: f64) -> Option<LightSample> {
+        let to_point = p - self.position;
+        let falloff = self.falloff(&to_point);
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            point: self.position,
+            normal: vector![0.0, 0.0, 0.0],
+            radiance: self.intensity * falloff / to_point.norm_squared(),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vector3<f64>, _wi: &Vector3<f64>) -> f64 {
+        0.0
+    }
+
+    fn sample_le(&self, time: f64) -> Option<EmissionSample> {
+        let direction = random_in_cone(&self.direction, self.cos_total_width);
+        Some(EmissionSample {
+            ray: Ray::new(self.position, direction, time),
+            normal: direction,
+            radiance: self.intensity * self.falloff(&direction),
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (2.0 * PI * (1.0 - self.cos_total_width)),
+        })
+    }
+
+    fn pdf_le(&self, r: &Ray, _normal: &Vector3<f64>) -> (f64, f64) {
+        let pdf_dir = if r.direction.normalize().dot(&self.direction) >= self.cos_total_width {
+            1.0 / (2.0 * PI * (1.0 - self.cos_total_width))
+        } else {
+            0.0
+        };
+        (0.0, pdf_dir)
+    }
+
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> bool {
+        false
+    }
+
+    fn is_delta_position(&self) -> bool {
+        true
+    }
+}
+
+/// A light infinitely far away, delivering `irradiance` to surfaces facing `direction`.
+pub struct DirectionalLight {
+    direction: Vector3<f64>,
+    irradiance: Vector3<f64>,
+    world_center: Vector3<f64>,
+    world_radius: f64,
+}
+
+impl DirectionalLight {
+    /// `direction` points towards the light. `world_bounds` must enclose the scene, so that
+    /// emitted rays can start outside it.
+    pub fn new(direction: Vector3<f64>, irradiance: Vector3<f64>, world_bounds: Aabb) -> Self {
+        let (world_center, world_radius) = world_bounds.bounding_sphere();
+        Self {
+            direction: direction.normalize(),
+            irradiance,
+            world_center,
+            world_radius,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, p: &Vector3<f64>, _time: f64) -> Option<LightSample> {
+        Some(LightSample {
+            point: p + 2.0 * self.world_radius * self.direction,
+            normal: vector![0.0, 0.0, 0.0],
+            radiance: self.irradiance,
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vector3<f64>, _wi: &Vector3<f64>) -> f64 {
+        0.0
+    }
+
+    fn sample_le(&self, time: f64) -> Option<EmissionSample> {
+        Some(EmissionSample {
+            ray: ray_from_infinity(&self.direction, &self.world_center, self.world_radius, time),
+            normal: -self.direction,
+            radiance: self.irradiance,
+            pdf_pos: 1.0 / (PI * self.world_radius.powi(2)),
+            pdf_dir: 1.0,
+        })
+    }
+
+    fn pdf_le(&self, _r: &Ray, _normal: &Vector3<f64>) -> (f64, f64) {
+        (1.0 / (PI * self.world_radius.powi(2)), 0.0)
+    }
+
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> bool {
+        false
+    }
+
+    fn is_delta_direction(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    use nalgebra::{vector, Vector3};
+
+    use super::{AreaLight, FitError};
+    use crate::aarect::XZRect;
+    use crate::hittable::Hittable;
+    use crate::material::{DiffuseLight, Lambertian, Material};
+    use crate::moving_sphere::MovingSphere;
+    use crate::ray::Ray;
+
+    /// The radiance leaving `panel`, seen from above its middle.
+    fn panel_radiance(panel: &XZRect) -> Vector3<f64> {
+        let r = Ray::new(vector![1.0, 1.0, 1.5], vector![0.0, -1.0, 0.0], 0.0);
+        let rec = panel.hit(&r, 0.001, f64::INFINITY).unwrap();
+        rec.material().emitted(&r, &rec)
+    }
+
+    fn panel(material: Arc<dyn Material>) -> Arc<XZRect> {
+        Arc::new(XZRect::new(0.0, 2.0, 0.0, 3.0, 0.0, material))
+    }
+
+    #[test]
+    fn power_spreads_over_the_area_and_sides() {
+        let two_sided = panel(Arc::new(DiffuseLight::new(vector![1.0, 1.0, 1.0])));
+        let one_sided = panel(Arc::new(
+            DiffuseLight::new(vector![1.0, 1.0, 1.0]).one_sided(),
+        ));
+        AreaLight::new(two_sided.clone()).with_power(60.0).unwrap();
+        AreaLight::new(one_sided.clone()).with_power(60.0).unwrap();
+
+        let expected = 60.0 / (PI * 6.0);
+        assert!((panel_radiance(&two_sided).x - expected / 2.0).abs() < 1e-9);
+        assert!((panel_radiance(&one_sided).x - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn unfitted_lights_emit_their_colour() {
+        let colour = vector![0.5, 1.0, 2.0];
+        let panel = panel(Arc::new(DiffuseLight::new(colour)));
+        AreaLight::new(panel.clone());
+        assert_eq!(panel_radiance(&panel), colour);
+    }
+
+    #[test]
+    fn lights_that_cant_be_fitted_are_errors() {
+        let shared: Arc<dyn Material> = Arc::new(DiffuseLight::new(vector![1.0, 1.0, 1.0]));
+        assert!(AreaLight::new(panel(shared.clone()))
+            .with_power(1.0)
+            .is_ok());
+        assert_eq!(
+            AreaLight::new(panel(shared)).with_power(1.0).err(),
+            Some(FitError::AlreadyFitted)
+        );
+
+        let lambertian = Arc::new(Lambertian::new(vector![1.0, 1.0, 1.0]));
+        assert_eq!(
+            AreaLight::new(panel(lambertian)).with_radiance(1.0).err(),
+            Some(FitError::NotEmissive)
+        );
+
+        let moving = MovingSphere::new(
+            vector![0.0, 0.0, 0.0],
+            vector![1.0, 0.0, 0.0],
+            0.0,
+            1.0,
+            1.0,
+            Arc::new(DiffuseLight::new(vector![1.0, 1.0, 1.0])),
+        );
+        assert_eq!(
+            AreaLight::new(Arc::new(moving)).with_power(1.0).err(),
+            Some(FitError::NoSurface)
+        );
+    }
+}
@@ -1,40 +1,192 @@
 mod aabb;
 mod aabox;
 mod aarect;
+mod asset;
+mod background;
+mod bdpt;
 mod bvh;
 mod camera;
 mod constant_medium;
+mod distribution;
+mod environment;
+mod film;
 mod hittable;
 mod hittable_list;
+mod ies;
+mod light;
 mod material;
+mod microfacet;
 mod moving_sphere;
 mod perlin;
+mod photon_map;
+mod ppm;
+mod procedural;
 mod random;
 mod ray;
 mod scenes;
+mod sky;
+mod spectrum;
 mod sphere;
+mod subsurface;
 mod texture;
+mod texture_cache;
+mod texture_graph;
+mod triangle;
+mod worley;
 
+use crate::asset::AssetError;
+use crate::background::Background;
+use crate::bdpt::Bdpt;
 use crate::camera::Camera;
-use crate::hittable::Hittable;
+use crate::environment::EnvironmentMap;
+use crate::film::Film;
+use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
-use crate::random::random_double;
+use crate::light::Light;
+use crate::ppm::{PixelEstimate, ProgressivePhotonMapper};
+use crate::random::{random_double, random_int};
 use crate::ray::Ray;
 use crate::scenes::random_scene;
-use clap::Parser;
+use crate::sky::PhysicalSky;
+use crate::spectrum::{xyz_to_rgb, SampledWavelengths};
+use crate::texture_cache::TextureCache;
+use clap::{Parser, ValueEnum};
 use image::RgbImage;
-use indicatif::ParallelProgressIterator;
-use nalgebra::{vector, Vector3};
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressIterator};
+use nalgebra::{vector, Vector3, Vector4};
 use rayon::prelude::*;
 use scenes::{
-    cornell_box, cornell_smoke, earth, final_scene, simple_light, two_perlin_spheres, two_spheres,
+    bumps, cornell_box, cornell_smoke, cutouts, delta_lights, diffuse, earth, filtering,
+    final_scene, ies_wall, illuminants, layered, materials, principled, procedural, simple_light,
+    subsurface, texture_graph, textured_lights, two_perlin_spheres, two_spheres,
 };
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
 
+/// Samples point, spot and directional lights, which scattered rays can never hit. Returns the
+/// BSDF over the sampling density and the incident radiance for each light that isn't occluded.
+fn delta_light_samples(
+    r: &Ray,
+    rec: &HitRecord,
+    world: &HittableList,
+    lights: &[Arc<dyn Light>],
+) -> Vec<(Vector3<f64>, Vector3<f64>)> {
+    if rec.material().is_specular(rec) {
+        return Vec::new();
+    }
+
+    lights
+        .iter()
+        .filter(|light| light.is_delta_position() || light.is_delta_direction())
+        .filter_map(|light| {
+            let sample = light.sample_li(&rec.point(), r.time)?;
+            let to_light = sample.point - rec.point();
+            let distance = to_light.norm();
+            let shadow_ray = Ray::new(rec.point(), to_light / distance, r.time);
+            if world.hit(&shadow_ray, 0.001, distance - 0.001).is_some() {
+                return None;
+            }
+            let f = rec.material().eval(r, rec, &shadow_ray) / sample.pdf;
+            Some((f, sample.radiance))
+        })
+        .collect()
+}
+
+/// Samples the lights at infinity, such as an environment map, as a single light that picks each
+/// of them equally. Returns the BSDF over the sampling density, weighted by the power heuristic
+/// against the BSDF picking the same direction, and the incident radiance.
+fn infinite_light_sample(
+    r: &Ray,
+    rec: &HitRecord,
+    background: &dyn Background,
+    world: &HittableList,
+    lights: &[Arc<dyn Light>],
+) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let count = lights.iter().filter(|light| light.is_infinite()).count();
+    if count == 0 || rec.material().is_specular(rec) {
+        return None;
+    }
+
+    let light = lights
+        .iter()
+        .filter(|light| light.is_infinite())
+        .nth(random_int(0, count as i32 - 1) as usize)?;
+    let sample = light.sample_li(&rec.point(), r.time)?;
+    let direction = (sample.point - rec.point()).normalize();
+    let shadow_ray = Ray::new(rec.point(), direction, r.time);
+    if world.hit(&shadow_ray, 0.001, f64::INFINITY).is_some() {
+        return None;
+    }
+    let light_pdf = infinite_light_pdf(&rec.point(), &direction, lights);
+    if light_pdf <= 0.0 {
+        return None;
+    }
+    let bsdf_pdf = rec.material().scattering_pdf(r, rec, &shadow_ray);
+    let f =
+        rec.material().eval(r, rec, &shadow_ray) * power_heuristic(light_pdf, bsdf_pdf) / light_pdf;
+    Some((f, background.value(&direction)))
+}
+
+/// The density with which `infinite_light_sample` picks `direction` from `p`.
+fn infinite_light_pdf(
+    p: &Vector3<f64>,
+    direction: &Vector3<f64>,
+    lights: &[Arc<dyn Light>],
+) -> f64 {
+    let (sum, count) = lights
+        .iter()
+        .filter(|light| light.is_infinite())
+        .fold((0.0, 0), |(sum, count), light| {
+            (sum + light.pdf_li(p, direction), count + 1)
+        });
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
+    }
+}
+
+/// The weight of a sample from a strategy with density `pdf` against another with `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    if other_pdf <= 0.0 {
+        return 1.0;
+    }
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
+}
+
+/// The background seen along `r`, weighted against lighting from infinity having been sampled at
+/// the hit that scattered `r` with density `bsdf_pdf`.
+fn escaped(
+    r: &Ray,
+    bsdf_pdf: Option<f64>,
+    background: &dyn Background,
+    lights: &[Arc<dyn Light>],
+) -> Vector3<f64> {
+    let radiance = background.value(&r.direction);
+    match bsdf_pdf {
+        Some(pdf) => {
+            let direction = r.direction.normalize();
+            radiance * power_heuristic(pdf, infinite_light_pdf(&r.origin, &direction, lights))
+        }
+        None => radiance,
+    }
+}
+
+/// The density with which the BSDF at `rec` picked `scattered`, for weighting against light
+/// sampling, or `None` for specular materials that light sampling can't stand in for.
+fn scattering_pdf(r: &Ray, rec: &HitRecord, scattered: &Ray) -> Option<f64> {
+    (!rec.material().is_specular(rec)).then(|| rec.material().scattering_pdf(r, rec, scattered))
+}
+
+/// The radiance along `r`. `bsdf_pdf` is the density with which the last hit picked `r`, if it
+/// also sampled lights at infinity.
 fn ray_colour(
     r: &Ray,
-    background: &Vector3<f64>,
+    bsdf_pdf: Option<f64>,
+    background: &dyn Background,
     world: &HittableList,
+    lights: &[Arc<dyn Light>],
     depth: i32,
 ) -> Vector3<f64> {
     if depth <= 0 {
@@ -42,15 +194,99 @@ fn ray_colour(
     }
 
     if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) {
-        let emitted = rec.material().emitted(rec.u(), rec.v(), &rec.point());
-        if let Some((attenuation, scatttered)) = rec.material().scatter(r, &rec) {
+        // Dispersion splits light up by wavelength, so trace the rest of the path spectrally.
+        if rec.material().is_dispersive() {
+            let mut wavelengths = SampledWavelengths::sample();
+            let radiance = spectral_ray_colour(
+                r,
+                bsdf_pdf,
+                &mut wavelengths,
+                background,
+                world,
+                lights,
+                depth,
+            );
+            return xyz_to_rgb(&wavelengths.to_xyz(&radiance));
+        }
+
+        let emitted = rec.material().emitted(r, &rec)
+            + delta_light_samples(r, &rec, world, lights)
+                .into_iter()
+                .chain(infinite_light_sample(r, &rec, background, world, lights))
+                .map(|(f, radiance)| f.component_mul(&radiance))
+                .sum::<Vector3<f64>>();
+        if let Some((attenuation, mut scattered)) = rec.material().scatter(r, &rec) {
+            if rec.material().is_specular(&rec) {
+                scattered.differentials = rec.scattered_differentials(r, &scattered);
+            }
             emitted
-                + attenuation.component_mul(&ray_colour(&scatttered, background, world, depth - 1))
+                + attenuation.component_mul(&ray_colour(
+                    &scattered,
+                    scattering_pdf(r, &rec, &scattered),
+                    background,
+                    world,
+                    lights,
+                    depth - 1,
+                ))
         } else {
             emitted
         }
     } else {
-        *background
+        escaped(r, bsdf_pdf, background, lights)
+    }
+}
+
+/// The radiance along `r` at each of `wavelengths`, uplifting the scene's RGB colours to spectra.
+fn spectral_ray_colour(
+    r: &Ray,
+    bsdf_pdf: Option<f64>,
+    wavelengths: &mut SampledWavelengths,
+    background: &dyn Background,
+    world: &HittableList,
+    lights: &[Arc<dyn Light>],
+    depth: i32,
+) -> Vector4<f64> {
+    if depth <= 0 {
+        return Vector4::zeros();
+    }
+
+    if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) {
+        let mut r = *r;
+        if rec.material().is_dispersive() && r.wavelength.is_none() {
+            wavelengths.terminate_secondary();
+            r.wavelength = Some(wavelengths.hero());
+        }
+
+        let mut emitted = rec.material().emitted_spectrum(&r, &rec, wavelengths);
+        for (f, radiance) in delta_light_samples(&r, &rec, world, lights)
+            .into_iter()
+            .chain(infinite_light_sample(&r, &rec, background, world, lights))
+        {
+            emitted += wavelengths
+                .uplift_reflectance(&f)
+                .component_mul(&wavelengths.uplift_illuminant(&radiance));
+        }
+        if let Some((attenuation, mut scattered)) = rec.material().scatter(&r, &rec) {
+            scattered.wavelength = r.wavelength;
+            if rec.material().is_specular(&rec) {
+                scattered.differentials = rec.scattered_differentials(&r, &scattered);
+            }
+            let attenuation = wavelengths.uplift_reflectance(&attenuation);
+            emitted
+                + attenuation.component_mul(&spectral_ray_colour(
+                    &scattered,
+                    scattering_pdf(&r, &rec, &scattered),
+                    wavelengths,
+                    background,
+                    world,
+                    lights,
+                    depth - 1,
+                ))
+        } else {
+            emitted
+        }
+    } else {
+        wavelengths.uplift_illuminant(&escaped(r, bsdf_pdf, background, lights))
     }
 }
 
@@ -63,6 +299,14 @@ fn vector_to_rgb(colour: &Vector3<f64>, samples: u32) -> [u8; 3] {
     ]
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum Integrator {
+    Path,
+    Bdpt,
+    Photon,
+    Spectral,
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Args {
@@ -70,11 +314,63 @@ struct Args {
     scene: usize,
     #[arg(default_value = "output.png")]
     path: PathBuf,
+    #[arg(long, value_enum, default_value_t = Integrator::Path)]
+    integrator: Integrator,
+    /// Photons traced per pass by the photon mapping integrator
+    #[arg(long, default_value_t = 200_000)]
+    photons: usize,
+    /// Initial gather radius for the photon mapping integrator, defaulting to a fraction of the
+    /// scene's extent
+    #[arg(long)]
+    gather_radius: Option<f64>,
+    /// Equirectangular .hdr or .exr image to light the scene with in place of its background
+    #[arg(long)]
+    environment: Option<PathBuf>,
+    /// Rotation of the environment about the vertical axis, in degrees
+    #[arg(long, default_value_t = 0.0)]
+    environment_rotation: f64,
+    /// Scale applied to the environment's radiance
+    #[arg(long, default_value_t = 1.0)]
+    environment_intensity: f64,
+    /// Light the scene with a physically based sky and sun in place of its background
+    #[arg(long, conflicts_with = "environment")]
+    sky: bool,
+    /// Elevation of the sun above the horizon, in degrees
+    #[arg(long, default_value_t = 45.0)]
+    sun_elevation: f64,
+    /// Azimuth of the sun about the vertical axis from +x towards +z, in degrees
+    #[arg(long, default_value_t = 0.0)]
+    sun_azimuth: f64,
+    /// Atmospheric turbidity, from about 2 for a very clear sky to 10 for a hazy one
+    #[arg(long, default_value_t = 3.0)]
+    turbidity: f64,
+    /// Memory to keep decoded image textures within, in MiB. The least recently used tiles of the
+    /// images are dropped to make room for others, and decoded again when needed. Images take 4
+    /// bytes a pixel, and decoding one again is slow, so the images in use should fit comfortably
+    #[arg(long)]
+    texture_budget: Option<usize>,
+    /// Render image textures that can't be read as a magenta checker, rather than stopping
+    #[arg(long)]
+    fallback_textures: bool,
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
+    TextureCache::init(
+        args.texture_budget.map(|mib| mib << 20),
+        args.fallback_textures,
+    );
+
+    match render(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
+fn render(args: Args) -> Result<(), AssetError> {
     // Image
 
     let mut aspect_ratio = 16.0 / 9.0;
@@ -85,6 +381,7 @@ fn main() {
     // World
 
     let world;
+    let mut lights: Vec<Arc<dyn Light>> = Vec::new();
 
     let lookfrom;
     let lookat;
@@ -116,14 +413,14 @@ fn main() {
             vfov = 20.0;
         }
         4 => {
-            world = earth();
+            world = earth()?;
             background = vector![0.70, 0.80, 1.00];
             lookfrom = vector![13.0, 2.0, 3.0];
             lookat = vector![0.0, 0.0, 0.0];
             vfov = 20.0;
         }
         5 => {
-            world = simple_light();
+            (world, lights) = simple_light();
             samples_per_pixel = 400;
             background = vector![0.0, 0.0, 0.0];
             lookfrom = vector![26.0, 3.0, 6.0];
@@ -131,7 +428,7 @@ fn main() {
             vfov = 20.0;
         }
         6 => {
-            world = cornell_box();
+            (world, lights) = cornell_box();
             aspect_ratio = 1.0;
             image_width = 600;
             samples_per_pixel = 200;
@@ -141,7 +438,7 @@ fn main() {
             vfov = 40.0;
         }
         7 => {
-            world = cornell_smoke();
+            (world, lights) = cornell_smoke();
             aspect_ratio = 1.0;
             image_width = 600;
             samples_per_pixel = 200;
@@ -149,8 +446,106 @@ fn main() {
             lookat = vector![278.0, 278.0, 0.0];
             vfov = 40.0;
         }
+        8 => {
+            (world, lights) = delta_lights();
+            background = vector![0.0, 0.0, 0.0];
+            lookfrom = vector![13.0, 4.0, 6.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 30.0;
+        }
+        9 => {
+            (world, lights) = textured_lights()?;
+            background = vector![0.0, 0.0, 0.0];
+            lookfrom = vector![0.0, 3.0, 18.0];
+            lookat = vector![0.0, 2.0, 0.0];
+            vfov = 30.0;
+        }
+        10 => {
+            (world, lights) = ies_wall()?;
+            background = vector![0.0, 0.0, 0.0];
+            lookfrom = vector![0.0, 3.5, 16.0];
+            lookat = vector![0.0, 3.0, -1.0];
+            vfov = 40.0;
+        }
+        11 => {
+            world = materials();
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        12 => {
+            (world, lights) = illuminants();
+            background = vector![0.0, 0.0, 0.0];
+            lookfrom = vector![0.0, 3.0, 14.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        13 => {
+            world = principled();
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        14 => {
+            world = layered();
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        15 => {
+            world = diffuse();
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        16 => {
+            world = subsurface();
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        17 => {
+            (world, lights) = bumps()?;
+            background = vector![0.25, 0.3, 0.4];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        18 => {
+            (world, lights) = cutouts()?;
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 3.0, 12.0];
+            lookat = vector![0.0, 1.0, 0.0];
+            vfov = 35.0;
+        }
+        19 => {
+            world = filtering()?;
+            background = vector![0.0, 0.0, 0.0];
+            lookfrom = vector![0.0, 2.1, 12.0];
+            lookat = vector![0.0, 2.1, 0.0];
+            vfov = 25.0;
+        }
+        20 => {
+            world = procedural();
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 9.0, 11.0];
+            lookat = vector![0.0, 0.5, -0.5];
+            vfov = 35.0;
+        }
+        21 => {
+            world = texture_graph()?;
+            background = vector![0.70, 0.80, 1.00];
+            lookfrom = vector![0.0, 9.0, 11.0];
+            lookat = vector![0.0, 0.5, -0.5];
+            vfov = 35.0;
+        }
         _ => {
-            world = final_scene();
+            (world, lights) = final_scene()?;
             aspect_ratio = 1.0;
             image_width = 800;
             samples_per_pixel = 10000;
@@ -161,6 +556,31 @@ fn main() {
         }
     }
 
+    let world_bounds = world.bounding_box(0.0, 1.0).unwrap_or_default();
+    let background: Arc<dyn Background> = match args.environment {
+        Some(path) => {
+            let environment = Arc::new(EnvironmentMap::from_image(
+                path,
+                args.environment_rotation,
+                args.environment_intensity,
+                world_bounds,
+            )?);
+            lights.push(environment.clone());
+            environment
+        }
+        None if args.sky => {
+            let sky = PhysicalSky::new(
+                args.sun_elevation,
+                args.sun_azimuth,
+                args.turbidity,
+                world_bounds,
+            );
+            lights.extend(sky.lights());
+            Arc::new(sky)
+        }
+        None => Arc::new(background),
+    };
+
     // Camera
 
     let vup = vector![0.0, 1.0, 0.0];
@@ -181,23 +601,87 @@ fn main() {
 
     // Render
 
-    let buffer: Vec<u8> = (0..image_height)
+    let film = Film::new(image_width, image_height);
+    let bdpt = Bdpt::new(&world, &lights, &cam, &*background, max_depth as usize);
+    let gather_radius = args.gather_radius.unwrap_or_else(|| {
+        world
+            .bounding_box(0.0, 1.0)
+            .map_or(1.0, |bbox| 0.002 * (bbox.maximum - bbox.minimum).norm())
+    });
+    let ppm =
+        ProgressivePhotonMapper::new(&world, &lights, &cam, &*background, max_depth, args.photons);
+
+    let positions: Vec<(u32, u32)> = (0..image_height)
         .flat_map(|j| (0..image_width).map(move |i| (i, j)))
-        .collect::<Vec<(_, _)>>()
-        .into_par_iter()
-        .progress()
-        .flat_map(|(x, y)| {
-            let mut pixel_colour = vector![0.0, 0.0, 0.0];
-            for _ in 0..samples_per_pixel {
-                let u = ((x as f64) + random_double()) / (image_width - 1) as f64;
-                let v = ((y as f64) - random_double()) / (image_height - 1) as f64;
-                let r = cam.get_ray(u, v);
-                pixel_colour += ray_colour(&r, &background, &world, max_depth);
+        .collect();
+
+    // Ray differentials span the spacing between samples, which shrinks as their number grows.
+    let spread = (1.0 / (samples_per_pixel as f64).sqrt()).max(0.125);
+    let ds = spread / (image_width - 1) as f64;
+    let dt = spread / (image_height - 1) as f64;
+
+    let pixels: Vec<Vector3<f64>> = match args.integrator {
+        Integrator::Path | Integrator::Bdpt | Integrator::Spectral => positions
+            .par_iter()
+            .progress()
+            .map(|&(x, y)| {
+                let mut pixel_colour = vector![0.0, 0.0, 0.0];
+                for _ in 0..samples_per_pixel {
+                    pixel_colour += match args.integrator {
+                        Integrator::Bdpt => bdpt.sample(x, y, &film),
+                        Integrator::Spectral => {
+                            let u = ((x as f64) + random_double()) / (image_width - 1) as f64;
+                            let v = ((y as f64) - random_double()) / (image_height - 1) as f64;
+                            let r = cam.get_ray_differential(u, v, ds, dt);
+                            let mut wavelengths = SampledWavelengths::sample();
+                            let radiance = spectral_ray_colour(
+                                &r,
+                                None,
+                                &mut wavelengths,
+                                &*background,
+                                &world,
+                                &lights,
+                                max_depth,
+                            );
+                            wavelengths.to_xyz(&radiance)
+                        }
+                        _ => {
+                            let u = ((x as f64) + random_double()) / (image_width - 1) as f64;
+                            let v = ((y as f64) - random_double()) / (image_height - 1) as f64;
+                            let r = cam.get_ray_differential(u, v, ds, dt);
+                            ray_colour(&r, None, &*background, &world, &lights, max_depth)
+                        }
+                    };
+                }
+                match args.integrator {
+                    Integrator::Spectral => xyz_to_rgb(&pixel_colour),
+                    _ => pixel_colour,
+                }
+            })
+            .collect(),
+        Integrator::Photon => {
+            let mut estimates = vec![PixelEstimate::new(gather_radius); positions.len()];
+            let progress = ProgressBar::new(samples_per_pixel as u64);
+            for _ in (0..samples_per_pixel).progress_with(progress) {
+                let photon_map = ppm.emit_photons();
+                estimates.par_iter_mut().zip(positions.par_iter()).for_each(
+                    |(estimate, &(x, y))| {
+                        ppm.sample(x, y, image_width, image_height, &photon_map, estimate);
+                    },
+                );
             }
-            vector_to_rgb(&pixel_colour, samples_per_pixel)
+            estimates.iter().map(PixelEstimate::total).collect()
+        }
+    };
+    let buffer: Vec<u8> = pixels
+        .iter()
+        .zip(positions)
+        .flat_map(|(pixel_colour, (x, y))| {
+            vector_to_rgb(&(pixel_colour + film.splat(x, y)), samples_per_pixel)
         })
         .collect();
     let img = RgbImage::from_raw(image_width, image_height, buffer).unwrap();
 
     img.save(args.path).unwrap();
+    Ok(())
 }
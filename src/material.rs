@@ -1,10 +1,15 @@
-use std::sync::Arc;
+use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
 
-use crate::hittable::HitRecord;
-use crate::random::{random_double, random_in_unit_sphere, random_unit_vector};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ies::IesProfile;
+use crate::light::{Emission, FitError};
+use crate::microfacet::{self, fresnel_conductor, fresnel_dielectric, Frame, TrowbridgeReitz};
+use crate::random::{hash_point, random_double, random_in_unit_sphere, random_unit_vector};
 use crate::ray::Ray;
-use crate::texture::{SolidColour, Texture};
-use nalgebra::{vector, Vector3};
+use crate::spectrum::{Dispersion, SampledWavelengths, Spectrum};
+use crate::texture::{luminance, IntoTexture, SolidColour, Texture};
+use nalgebra::{vector, Vector3, Vector4};
 
 fn reflect(v: &Vector3<f64>, n: &Vector3<f64>) -> Vector3<f64> {
     v - 2.0 * v.dot(n) * n
@@ -18,11 +23,72 @@ fn refract(uv: &Vector3<f64>, n: &Vector3<f64>, etai_over_etat: f64) -> Vector3<
 }
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)>;
+    /// Picks a direction to continue along and the attenuation along it. Materials that only
+    /// choose between others are resolved before this is called, and needn't implement it.
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        None
+    }
+
+    /// The BSDF for light arriving along `r_in` and leaving along `scattered`, including the
+    /// cosine of the outgoing direction. Specular materials can't be evaluated and return zero.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Vector3<f64> {
+        vector![0.0, 0.0, 0.0]
+    }
+
+    /// The solid angle density with which `scatter` picks `scattered`.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// Whether `scatter` picks directions at `rec` from a delta distribution that `eval` can't
+    /// express.
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+
+    /// Whether the material scatters inside a participating medium rather than at a surface.
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+
+    /// Whether scattering depends on the wavelength of `r_in`, so that integrators must give the
+    /// ray one before calling `scatter`.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Vector3<f64>) -> Vector3<f64> {
+    /// The radiance emitted back along `r_in`.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vector3<f64> {
         vector![0.0, 0.0, 0.0]
     }
+
+    /// For materials that stand in for one of several others, the one in effect at `rec`.
+    /// Integrators only see the result, through `HitRecord::material`.
+    fn choose(&self, _rec: &HitRecord) -> Option<&Arc<dyn Material>> {
+        None
+    }
+
+    /// For materials whose emission can be scaled, scales it to `emission` over the surface of
+    /// `shape`, which this material covers. Called by `AreaLight::with_power` and `with_radiance`.
+    fn fit_emission(&self, _shape: &dyn Hittable, _emission: Emission) -> Result<(), FitError> {
+        Err(FitError::NotEmissive)
+    }
+
+    /// For materials with a normal or bump map, the shading normal at `rec`, facing the same way
+    /// as `rec.normal()`. Materials see it through `HitRecord::shading_normal`.
+    fn shading_normal(&self, _rec: &HitRecord) -> Option<Vector3<f64>> {
+        None
+    }
+
+    /// The radiance emitted back along `r_in` at each of `wavelengths`.
+    fn emitted_spectrum(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> Vector4<f64> {
+        wavelengths.uplift_illuminant(&self.emitted(r_in, rec))
+    }
 }
 
 pub struct Lambertian {
@@ -43,16 +109,182 @@ impl Lambertian {
 
 impl Material for Lambertian {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-        let mut scatter_direction = rec.normal() + random_unit_vector();
+        let scatter_direction = cosine_direction(&rec.shading_normal());
+        let attenuation = self.albedo.value_at(rec);
+        let scattered = Ray::new(rec.point(), scatter_direction, r_in.time);
+        Some((attenuation, scattered))
+    }
 
-        // Catch degenerate scatter direction
-        if scatter_direction.norm() < 1e-7 {
-            scatter_direction = rec.normal();
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        self.albedo.value_at(rec) * self.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos_in = -r_in.direction.normalize().dot(&rec.shading_normal());
+        let cos_out = scattered.direction.normalize().dot(&rec.shading_normal());
+        if cos_in * cos_out > 0.0 {
+            cos_out.abs() / PI
+        } else {
+            0.0
         }
+    }
 
-        let attenuation = self.albedo.value(rec.u(), rec.v(), &rec.point());
-        let scattered = Ray::new(rec.point(), scatter_direction, r_in.time);
-        Some((attenuation, scattered))
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+}
+
+/// A cosine-weighted direction about `normal`.
+fn cosine_direction(normal: &Vector3<f64>) -> Vector3<f64> {
+    let direction = normal + random_unit_vector();
+    if direction.norm() < 1e-7 {
+        *normal
+    } else {
+        direction
+    }
+}
+
+/// Rough diffuse reflection from a surface of tiny Lambertian facets, which looks flatter and
+/// brighter towards the light than `Lambertian`. Suits clay, concrete and the moon.
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    sigma: Arc<dyn Texture>,
+}
+
+impl OrenNayar {
+    /// `sigma` is the standard deviation of the facets' slope angle in radians, read from the
+    /// texture's luminance. At zero, this is `Lambertian`.
+    pub fn new(albedo: impl IntoTexture, sigma: impl IntoTexture) -> Self {
+        Self {
+            albedo: albedo.into_texture(),
+            sigma: sigma.into_texture(),
+        }
+    }
+
+    /// The reflectance relative to a Lambertian surface of the same albedo.
+    fn roughness_factor(&self, rec: &HitRecord, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let normal = rec.shading_normal();
+        let sigma = luminance(&self.sigma.value_at(rec)).max(0.0);
+        let sigma2 = sigma * sigma;
+        let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        // sin(alpha) tan(beta) cos(phi_i - phi_o), from the tangential parts of the directions.
+        let (cos_o, cos_i) = (wo.dot(&normal), wi.dot(&normal).abs());
+        let tangential = (wo - normal * cos_o).dot(&(wi - normal * wi.dot(&normal)));
+        a + b * tangential.max(0.0) / cos_o.max(cos_i).max(1e-7)
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let direction = cosine_direction(&rec.shading_normal());
+        let factor =
+            self.roughness_factor(rec, &-r_in.direction.normalize(), &direction.normalize());
+        let attenuation = self.albedo.value_at(rec) * factor;
+        Some((attenuation, Ray::new(rec.point(), direction, r_in.time)))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        let pdf = self.scattering_pdf(r_in, rec, scattered);
+        if pdf == 0.0 {
+            return vector![0.0, 0.0, 0.0];
+        }
+        let factor = self.roughness_factor(
+            rec,
+            &-r_in.direction.normalize(),
+            &scattered.direction.normalize(),
+        );
+        self.albedo.value_at(rec) * factor * pdf
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos_in = -r_in.direction.normalize().dot(&rec.shading_normal());
+        let cos_out = scattered.direction.normalize().dot(&rec.shading_normal());
+        if cos_in * cos_out > 0.0 {
+            cos_out.abs() / PI
+        } else {
+            0.0
+        }
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+}
+
+/// A thin diffuse sheet that lets some light through, scattering it evenly on the far side. Suits
+/// leaves, paper and lampshades.
+pub struct Translucent {
+    reflectance: Arc<dyn Texture>,
+    transmittance: Arc<dyn Texture>,
+}
+
+impl Translucent {
+    pub fn new(reflectance: impl IntoTexture, transmittance: impl IntoTexture) -> Self {
+        Self {
+            reflectance: reflectance.into_texture(),
+            transmittance: transmittance.into_texture(),
+        }
+    }
+
+    /// The reflectance and transmittance at `rec`, with the probability of sampling reflection.
+    fn colours(&self, rec: &HitRecord) -> (Vector3<f64>, Vector3<f64>, f64) {
+        let reflectance = self.reflectance.value_at(rec);
+        let transmittance = self.transmittance.value_at(rec);
+        let total = luminance(&reflectance) + luminance(&transmittance);
+        let reflect_probability = if total > 0.0 {
+            luminance(&reflectance) / total
+        } else {
+            0.5
+        };
+        (reflectance, transmittance, reflect_probability)
+    }
+}
+
+impl Material for Translucent {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let (reflectance, transmittance, reflect_probability) = self.colours(rec);
+        let (attenuation, direction) = if random_double() < reflect_probability {
+            (
+                reflectance / reflect_probability,
+                cosine_direction(&rec.shading_normal()),
+            )
+        } else {
+            (
+                transmittance / (1.0 - reflect_probability),
+                cosine_direction(&-rec.shading_normal()),
+            )
+        };
+        Some((attenuation, Ray::new(rec.point(), direction, r_in.time)))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        let (reflectance, transmittance, _) = self.colours(rec);
+        let cos_in = -r_in.direction.normalize().dot(&rec.shading_normal());
+        let cos_out = scattered.direction.normalize().dot(&rec.shading_normal());
+        let colour = if cos_in * cos_out > 0.0 {
+            reflectance
+        } else {
+            transmittance
+        };
+        colour * cos_out.abs() / PI
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let (_, _, reflect_probability) = self.colours(rec);
+        let cos_in = -r_in.direction.normalize().dot(&rec.shading_normal());
+        let cos_out = scattered.direction.normalize().dot(&rec.shading_normal());
+        let side_probability = if cos_in * cos_out > 0.0 {
+            reflect_probability
+        } else {
+            1.0 - reflect_probability
+        };
+        side_probability * cos_out.abs() / PI
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
     }
 }
 
@@ -69,7 +301,7 @@ impl Metal {
 
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-        let reflected = reflect(&r_in.direction.normalize(), &rec.normal());
+        let reflected = reflect(&r_in.direction.normalize(), &rec.shading_normal());
         let scattered = Ray::new(
             rec.point(),
             reflected + self.fuzz * random_in_unit_sphere(),
@@ -83,13 +315,147 @@ impl Material for Metal {
     }
 }
 
+/// A metal with a GGX microfacet surface and a complex index of refraction `eta + ik` per
+/// colour channel.
+pub struct Conductor {
+    eta: Vector3<f64>,
+    k: Vector3<f64>,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3<f64>, k: Vector3<f64>, roughness: f64) -> Self {
+        Self {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(roughness),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            vector![0.143, 0.374, 1.442],
+            vector![3.983, 2.385, 1.603],
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            vector![0.200, 0.924, 1.102],
+            vector![3.912, 2.452, 2.142],
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            vector![1.657, 0.880, 0.521],
+            vector![9.224, 6.270, 4.837],
+            roughness,
+        )
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Vector3<f64> {
+        vector![
+            fresnel_conductor(cos_theta, self.eta.x, self.k.x),
+            fresnel_conductor(cos_theta, self.eta.y, self.k.y),
+            fresnel_conductor(cos_theta, self.eta.z, self.k.z)
+        ]
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let frame = Frame::new(&rec.shading_normal());
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.is_smooth() {
+            let wi = vector![-wo.x, -wo.y, wo.z];
+            let scattered = Ray::new(rec.point(), frame.to_world(&wi), r_in.time);
+            return Some((self.fresnel(wo.z), scattered));
+        }
+
+        let wm = self
+            .distribution
+            .sample_wm(&wo, random_double(), random_double());
+        let wi = 2.0 * wo.dot(&wm) * wm - wo;
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        // The visible normal pdf cancels most of the BRDF, leaving F * G / G1(wo).
+        let attenuation =
+            self.fresnel(wo.dot(&wm)) * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        let scattered = Ray::new(rec.point(), frame.to_world(&wi), r_in.time);
+        Some((attenuation, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        let frame = Frame::new(&rec.shading_normal());
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        let wi = frame.to_local(&scattered.direction.normalize());
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return vector![0.0, 0.0, 0.0];
+        }
+
+        let wm = (wo + wi).normalize();
+        self.fresnel(wo.dot(&wm))
+            * (self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4.0 * wo.z))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = Frame::new(&rec.shading_normal());
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        let wi = frame.to_local(&scattered.direction.normalize());
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let wm = (wo + wi).normalize();
+        self.distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
 pub struct Dielectric {
     pub ir: f64,
+    pub absorption: Vector3<f64>,
+    pub dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Self {
-        Self { ir }
+        Self {
+            ir,
+            absorption: vector![0.0, 0.0, 0.0],
+            dispersion: None,
+        }
+    }
+
+    /// Varies the index of refraction with wavelength, splitting white light into its colours.
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.ir = dispersion.nominal_ior();
+        self.dispersion = Some(dispersion);
+        self
+    }
+
+    /// Attenuates light travelling through the interior by `exp(-absorption * distance)`.
+    pub fn with_absorption(mut self, absorption: Vector3<f64>) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    /// Sets the absorption so that light travelling `distance` through the interior is left with
+    /// the fraction `colour` of each channel.
+    pub fn with_transmittance(self, colour: Vector3<f64>, distance: f64) -> Self {
+        self.with_absorption(colour.map(|c| -c.max(1e-6).ln() / distance))
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -97,43 +463,880 @@ impl Dielectric {
         let r0 = ((1.0 - ref_idx) / (1.0 + ref_idx)).powi(2);
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
+
+    fn ior(&self, r_in: &Ray) -> f64 {
+        match (self.dispersion, r_in.wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ir,
+        }
+    }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
-        let refraction_ratio = if rec.front_face() {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+        let ir = self.ior(r_in);
+        let refraction_ratio = if rec.front_face() { 1.0 / ir } else { ir };
 
         let unit_direction = r_in.direction.normalize();
-        let cos_theta = rec.normal().dot(&-unit_direction).min(1.0);
+        let cos_theta = rec.shading_normal().dot(&-unit_direction).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let direction =
             if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_double() {
-                reflect(&unit_direction, &rec.normal())
+                reflect(&unit_direction, &rec.shading_normal())
             } else {
-                refract(&unit_direction, &rec.normal(), refraction_ratio)
+                refract(&unit_direction, &rec.shading_normal(), refraction_ratio)
             };
 
+        // A ray hitting the back of the surface has travelled through the interior to get here.
+        let attenuation = if rec.front_face() {
+            vector![1.0, 1.0, 1.0]
+        } else {
+            let distance = rec.t() * r_in.direction.norm();
+            (-self.absorption * distance).map(f64::exp)
+        };
+
         let scattered = Ray::new(rec.point(), direction, r_in.time);
-        Some((vector![1.0, 1.0, 1.0], scattered))
+        Some((attenuation, scattered))
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+/// Glass with a GGX microfacet interface, for frosted or etched surfaces. For perfectly smooth
+/// glass, use `Dielectric`.
+pub struct RoughDielectric {
+    ir: f64,
+    roughness: Arc<dyn Texture>,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> Self {
+        Self::new_from_texture(
+            ir,
+            Arc::new(SolidColour::new(vector![1.0, 1.0, 1.0] * roughness)),
+        )
+    }
+
+    /// Takes the roughness at each point from the luminance of `roughness`.
+    pub fn new_from_texture(ir: f64, roughness: Arc<dyn Texture>) -> Self {
+        Self { ir, roughness }
+    }
+
+    /// The local frame, the microfacet distribution, and the ratio of indices of refraction
+    /// across the interface, as seen from the side that `rec` was hit on.
+    fn interface(&self, rec: &HitRecord) -> (Frame, TrowbridgeReitz, f64) {
+        let roughness = luminance(&self.roughness.value_at(rec));
+        let eta = if rec.front_face() {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        (
+            Frame::new(&rec.shading_normal()),
+            TrowbridgeReitz::new(roughness),
+            eta,
+        )
+    }
+}
+
+/// The scale of light refracted across an interface with the ratio of indices `eta`. Radiance is
+/// squeezed into a narrower cone of directions, and scales by `1 / eta²`, while the importance
+/// carried by rays from lights doesn't scale.
+fn radiance_scale(r_in: &Ray, eta: f64) -> f64 {
+    if r_in.from_light {
+        1.0
+    } else {
+        1.0 / (eta * eta)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let (frame, distribution, eta) = self.interface(rec);
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let wm = distribution.sample_wm(&wo, random_double(), random_double());
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+
+        let (wi, attenuation) = if random_double() < reflectance {
+            let wi = 2.0 * wo.dot(&wm) * wm - wo;
+            if wi.z <= 0.0 {
+                return None;
+            }
+            (wi, distribution.g(&wo, &wi) / distribution.g1(&wo))
+        } else {
+            let wi = microfacet::refract(&wo, &wm, eta)?;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            (
+                wi,
+                distribution.g(&wo, &wi) / distribution.g1(&wo) * radiance_scale(r_in, eta),
+            )
+        };
+
+        let scattered = Ray::new(rec.point(), frame.to_world(&wi), r_in.time);
+        Some((vector![1.0, 1.0, 1.0] * attenuation, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        let (frame, distribution, eta) = self.interface(rec);
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        let wi = frame.to_local(&scattered.direction.normalize());
+        let wm = match microfacet::half_vector(&wo, &wi, eta) {
+            Some(wm) if wo.z > 0.0 && wi.z != 0.0 => wm,
+            _ => return vector![0.0, 0.0, 0.0],
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        let value = if wi.z > 0.0 {
+            distribution.d(&wm) * distribution.g(&wo, &wi) * reflectance / (4.0 * wo.z)
+        } else {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            distribution.d(&wm)
+                * distribution.g(&wo, &wi)
+                * (1.0 - reflectance)
+                * (wi.dot(&wm) * wo.dot(&wm)).abs()
+                * radiance_scale(r_in, eta)
+                / (wo.z * denom)
+        };
+        vector![1.0, 1.0, 1.0] * value
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let (frame, distribution, eta) = self.interface(rec);
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        let wi = frame.to_local(&scattered.direction.normalize());
+        let wm = match microfacet::half_vector(&wo, &wi, eta) {
+            Some(wm) if wo.z > 0.0 && wi.z != 0.0 => wm,
+            _ => return 0.0,
+        };
+
+        let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+        if wi.z > 0.0 {
+            distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm)) * reflectance
+        } else {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            distribution.pdf(&wo, &wm) * wi.dot(&wm).abs() / denom * (1.0 - reflectance)
+        }
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+}
+
+/// An uber-material after Burley's "Physically Based Shading at Disney", with parameters that
+/// correspond to those of glTF and USD materials. Scalar parameters are read from the luminance of
+/// their textures.
+pub struct Principled {
+    base_colour: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    specular_tint: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    sheen_tint: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_roughness: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    anisotropy: Arc<dyn Texture>,
+    ior: f64,
+}
+
+impl Principled {
+    /// A rough dielectric of the given colour, which the other parameters build on.
+    pub fn new(base_colour: impl IntoTexture) -> Self {
+        Self {
+            base_colour: base_colour.into_texture(),
+            metallic: 0.0.into_texture(),
+            roughness: 0.5.into_texture(),
+            specular: 0.5.into_texture(),
+            specular_tint: 0.0.into_texture(),
+            sheen: 0.0.into_texture(),
+            sheen_tint: 0.5.into_texture(),
+            clearcoat: 0.0.into_texture(),
+            clearcoat_roughness: 0.03.into_texture(),
+            transmission: 0.0.into_texture(),
+            anisotropy: 0.0.into_texture(),
+            ior: 1.5,
+        }
+    }
+
+    /// Blends from a dielectric to a metal whose reflectance is the base colour.
+    pub fn with_metallic(mut self, metallic: impl IntoTexture) -> Self {
+        self.metallic = metallic.into_texture();
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: impl IntoTexture) -> Self {
+        self.roughness = roughness.into_texture();
+        self
+    }
+
+    /// Scales the reflectance of the dielectric, where 0.5 is that of the index of refraction.
+    pub fn with_specular(mut self, specular: impl IntoTexture) -> Self {
+        self.specular = specular.into_texture();
+        self
+    }
+
+    /// Tints the dielectric's reflections towards the base colour.
+    pub fn with_specular_tint(mut self, specular_tint: impl IntoTexture) -> Self {
+        self.specular_tint = specular_tint.into_texture();
+        self
+    }
+
+    /// Adds a soft glow at grazing angles, for cloth.
+    pub fn with_sheen(mut self, sheen: impl IntoTexture) -> Self {
+        self.sheen = sheen.into_texture();
+        self
+    }
+
+    /// Tints the sheen towards the base colour.
+    pub fn with_sheen_tint(mut self, sheen_tint: impl IntoTexture) -> Self {
+        self.sheen_tint = sheen_tint.into_texture();
+        self
+    }
+
+    /// Adds a second, colourless specular layer, such as a varnish.
+    pub fn with_clearcoat(mut self, clearcoat: impl IntoTexture) -> Self {
+        self.clearcoat = clearcoat.into_texture();
+        self
+    }
+
+    pub fn with_clearcoat_roughness(mut self, clearcoat_roughness: impl IntoTexture) -> Self {
+        self.clearcoat_roughness = clearcoat_roughness.into_texture();
+        self
+    }
+
+    /// Blends from an opaque surface to glass tinted by the base colour.
+    pub fn with_transmission(mut self, transmission: impl IntoTexture) -> Self {
+        self.transmission = transmission.into_texture();
+        self
+    }
+
+    /// Stretches highlights along the surface's first tangent, for brushed metal.
+    pub fn with_anisotropy(mut self, anisotropy: impl IntoTexture) -> Self {
+        self.anisotropy = anisotropy.into_texture();
+        self
+    }
+
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    fn lobes(&self, rec: &HitRecord) -> PrincipledLobes {
+        let scalar = |texture: &Arc<dyn Texture>| luminance(&texture.value_at(rec)).clamp(0.0, 1.0);
+        let white = vector![1.0, 1.0, 1.0];
+
+        let base_colour = self.base_colour.value_at(rec);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+        let clearcoat = scalar(&self.clearcoat);
+
+        let tint = if luminance(&base_colour) > 0.0 {
+            base_colour / luminance(&base_colour)
+        } else {
+            white
+        };
+        let r0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+        let diffuse = (1.0 - metallic) * (1.0 - transmission);
+
+        // Pick lobes roughly in proportion to how much light they scatter.
+        let weights = [diffuse, 1.0, clearcoat];
+        let total: f64 = weights.iter().sum();
+
+        PrincipledLobes {
+            frame: Frame::new(&rec.shading_normal()),
+            base_colour,
+            metallic,
+            roughness,
+            transmission,
+            diffuse,
+            sheen: scalar(&self.sheen) * white.lerp(&tint, scalar(&self.sheen_tint)),
+            specular_f0: 2.0
+                * r0
+                * scalar(&self.specular)
+                * white.lerp(&tint, scalar(&self.specular_tint)),
+            specular: TrowbridgeReitz::anisotropic(roughness, scalar(&self.anisotropy)),
+            clearcoat,
+            clearcoat_distribution: TrowbridgeReitz::new(scalar(&self.clearcoat_roughness)),
+            eta: if rec.front_face() {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+            probabilities: weights.map(|weight| weight / total),
+        }
+    }
+}
+
+/// The principled BSDF's parameters at a hit point, with directions in the local `frame`.
+struct PrincipledLobes {
+    frame: Frame,
+    base_colour: Vector3<f64>,
+    metallic: f64,
+    roughness: f64,
+    transmission: f64,
+    diffuse: f64,
+    sheen: Vector3<f64>,
+    specular_f0: Vector3<f64>,
+    specular: TrowbridgeReitz,
+    clearcoat: f64,
+    clearcoat_distribution: TrowbridgeReitz,
+    eta: f64,
+    // The chances of sampling the diffuse, specular and clearcoat lobes.
+    probabilities: [f64; 3],
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+impl PrincipledLobes {
+    /// The specular reflectance for a microfacet at `cos_theta` to `wo`. Transmissive parts use
+    /// the exact dielectric Fresnel term, so that light inside total internally reflects.
+    fn reflectance(&self, cos_theta: f64) -> Vector3<f64> {
+        let white = vector![1.0, 1.0, 1.0];
+        let schlick = |f0: &Vector3<f64>| f0 + (white - f0) * schlick_weight(cos_theta);
+        let dielectric = (1.0 - self.transmission) * schlick(&self.specular_f0)
+            + self.transmission * fresnel_dielectric(cos_theta, self.eta) * white;
+        self.metallic * schlick(&self.base_colour) + (1.0 - self.metallic) * dielectric
+    }
+
+    fn transmittance(&self, cos_theta: f64) -> f64 {
+        (1.0 - self.metallic) * self.transmission * (1.0 - fresnel_dielectric(cos_theta, self.eta))
+    }
+
+    /// The chance of reflecting rather than refracting through a microfacet.
+    fn reflect_probability(&self, cos_theta: f64) -> f64 {
+        let reflectance = self.reflectance(cos_theta).mean();
+        let total = reflectance + self.transmittance(cos_theta);
+        if total > 0.0 {
+            reflectance / total
+        } else {
+            1.0
+        }
+    }
+
+    fn sample(&self, wo: &Vector3<f64>) -> Option<Vector3<f64>> {
+        let [diffuse, specular, _] = self.probabilities;
+        let u = random_double();
+        if u < diffuse {
+            let wi = vector![0.0, 0.0, 1.0] + random_unit_vector();
+            return Some(if wi.norm_squared() < 1e-16 {
+                vector![0.0, 0.0, 1.0]
+            } else {
+                wi.normalize()
+            });
+        }
+
+        let distribution = if u < diffuse + specular {
+            &self.specular
+        } else {
+            &self.clearcoat_distribution
+        };
+        let wm = distribution.sample_wm(wo, random_double(), random_double());
+        if u >= diffuse + specular || random_double() < self.reflect_probability(wo.dot(&wm)) {
+            let wi = 2.0 * wo.dot(&wm) * wm - wo;
+            (wi.z > 0.0).then_some(wi)
+        } else {
+            microfacet::refract(wo, &wm, self.eta).filter(|wi| wi.z < 0.0)
+        }
+    }
+
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        let wm = match microfacet::half_vector(wo, wi, self.eta) {
+            Some(wm) if wo.z > 0.0 && wi.z != 0.0 => wm,
+            _ => return vector![0.0, 0.0, 0.0],
+        };
+
+        if wi.z < 0.0 {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / self.eta).powi(2);
+            return self.base_colour
+                * (self.specular.d(&wm)
+                    * self.specular.g(wo, wi)
+                    * self.transmittance(wo.dot(&wm))
+                    * (wi.dot(&wm) * wo.dot(&wm)).abs()
+                    / (wo.z * denom * self.eta));
+        }
+
+        let cos_d = wi.dot(&wm);
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let diffuse = self.diffuse
+            * wi.z
+            * (self.base_colour * retro / PI + self.sheen * schlick_weight(cos_d));
+
+        let specular = self.reflectance(wo.dot(&wm))
+            * (self.specular.d(&wm) * self.specular.g(wo, wi) / (4.0 * wo.z));
+
+        let clearcoat = self.clearcoat
+            * (0.04 + 0.96 * schlick_weight(wo.dot(&wm)))
+            * self.clearcoat_distribution.d(&wm)
+            * self.clearcoat_distribution.g(wo, wi)
+            / (4.0 * wo.z);
+
+        diffuse + specular + vector![1.0, 1.0, 1.0] * clearcoat
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let wm = match microfacet::half_vector(wo, wi, self.eta) {
+            Some(wm) if wo.z > 0.0 && wi.z != 0.0 => wm,
+            _ => return 0.0,
+        };
+        let [diffuse, specular, clearcoat] = self.probabilities;
+        let reflect_probability = self.reflect_probability(wo.dot(&wm));
+
+        if wi.z < 0.0 {
+            let denom = (wi.dot(&wm) + wo.dot(&wm) / self.eta).powi(2);
+            return specular * self.specular.pdf(wo, &wm) * wi.dot(&wm).abs() / denom
+                * (1.0 - reflect_probability);
+        }
+
+        diffuse * wi.z / PI
+            + specular * self.specular.pdf(wo, &wm) / (4.0 * wo.dot(&wm)) * reflect_probability
+            + clearcoat * self.clearcoat_distribution.pdf(wo, &wm) / (4.0 * wo.dot(&wm))
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let lobes = self.lobes(rec);
+        let wo = lobes.frame.to_local(&-r_in.direction.normalize());
+        let wi = lobes.sample(&wo)?;
+
+        let pdf = lobes.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let scattered = Ray::new(rec.point(), lobes.frame.to_world(&wi), r_in.time);
+        Some((lobes.eval(&wo, &wi) / pdf, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        let lobes = self.lobes(rec);
+        let wo = lobes.frame.to_local(&-r_in.direction.normalize());
+        let wi = lobes.frame.to_local(&scattered.direction.normalize());
+        lobes.eval(&wo, &wi)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let lobes = self.lobes(rec);
+        let wo = lobes.frame.to_local(&-r_in.direction.normalize());
+        let wi = lobes.frame.to_local(&scattered.direction.normalize());
+        lobes.pdf(&wo, &wi)
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+}
+
+/// Blends two materials, with `weight` giving the fraction of `b`. Each point takes on one of
+/// them, picked by hashing its position, so that the blend shows up as an average over an area.
+pub struct MixMaterial {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+    /// Reads the weight at each point from the luminance of `weight`.
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: impl IntoTexture) -> Self {
+        Self {
+            a,
+            b,
+            weight: weight.into_texture(),
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn choose(&self, rec: &HitRecord) -> Option<&Arc<dyn Material>> {
+        let weight = luminance(&self.weight.value_at(rec));
+        Some(if hash_point(&rec.point()) < weight {
+            &self.b
+        } else {
+            &self.a
+        })
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.choose(rec).unwrap().is_specular(rec)
+    }
+}
+
+/// The outward normal at `rec` with unit vectors along `rec.dpdu()` and across it, or `None` if
+/// the surface has no tangents.
+fn tangent_frame(rec: &HitRecord) -> Option<(Vector3<f64>, Vector3<f64>, Vector3<f64>)> {
+    let normal = if rec.front_face() {
+        rec.normal()
+    } else {
+        -rec.normal()
+    };
+    let tangent = rec.dpdu() - normal * normal.dot(&rec.dpdu());
+    if tangent.norm() < 1e-12 {
+        return None;
+    }
+    let tangent = tangent.normalize();
+    Some((normal, tangent, normal.cross(&tangent)))
+}
+
+/// Turns an outward shading normal to face the same way as `rec.normal()`.
+fn facing(normal: Vector3<f64>, rec: &HitRecord) -> Vector3<f64> {
+    if rec.front_face() {
+        normal
+    } else {
+        -normal
+    }
+}
+
+/// Shades `base` with normals from a tangent-space normal map, such as an `ImageTexture` loaded
+/// from a file, whose red, green and blue give the components along u, across it and outwards.
+pub struct NormalMapped {
+    base: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+}
+
+impl NormalMapped {
+    pub fn new(base: Arc<dyn Material>, map: impl IntoTexture) -> Self {
+        Self {
+            base,
+            map: map.into_texture(),
+        }
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        self.base.scatter(r_in, rec)
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.base.is_specular(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn choose(&self, _rec: &HitRecord) -> Option<&Arc<dyn Material>> {
+        Some(&self.base)
+    }
+
+    fn shading_normal(&self, rec: &HitRecord) -> Option<Vector3<f64>> {
+        let (normal, tangent, bitangent) = tangent_frame(rec)?;
+        let colour = self.map.value_at(rec);
+        let local = colour * 2.0 - vector![1.0, 1.0, 1.0];
+        let shading = tangent * local.x + bitangent * local.y + normal * local.z;
+        Some(facing(shading.normalize(), rec))
+    }
+}
+
+/// Shades `base` as though its surface were displaced outwards by the luminance of `bump` times
+/// `scale`, without moving the geometry.
+pub struct BumpMapped {
+    base: Arc<dyn Material>,
+    bump: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl BumpMapped {
+    pub fn new(base: Arc<dyn Material>, bump: impl IntoTexture, scale: f64) -> Self {
+        Self {
+            base,
+            bump: bump.into_texture(),
+            scale,
+        }
+    }
+}
+
+impl Material for BumpMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        self.base.scatter(r_in, rec)
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.base.is_specular(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn choose(&self, _rec: &HitRecord) -> Option<&Arc<dyn Material>> {
+        Some(&self.base)
+    }
+
+    fn shading_normal(&self, rec: &HitRecord) -> Option<Vector3<f64>> {
+        const DELTA: f64 = 0.0005;
+
+        let (normal, _, _) = tangent_frame(rec)?;
+        let (u, v, p) = (rec.u(), rec.v(), rec.point());
+        let (dpdu, dpdv) = (rec.dpdu(), rec.dpdv());
+        let height = |u, v, p| self.scale * luminance(&self.bump.value(u, v, &p));
+
+        // Differentiate the displaced surface by finite differences along u and v.
+        let displacement = height(u, v, p);
+        let du = (height(u + DELTA, v, p + DELTA * dpdu) - displacement) / DELTA;
+        let dv = (height(u, v + DELTA, p + DELTA * dpdv) - displacement) / DELTA;
+        let shading = (dpdu + normal * du).cross(&(dpdv + normal * dv));
+        if shading.norm() < 1e-12 {
+            return None;
+        }
+
+        let shading = shading.normalize();
+        let shading = if shading.dot(&normal) < 0.0 {
+            -shading
+        } else {
+            shading
+        };
+        Some(facing(shading, rec))
+    }
+}
+
+/// A clear dielectric coat over another material, such as varnish over wood or the clear coat of
+/// car paint. Light reaches the base through a smooth interface, losing some of its energy to
+/// absorption on the way through the coat and back. The base is treated as opaque.
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ir: f64,
+    distribution: TrowbridgeReitz,
+    absorption: Vector3<f64>,
+    thickness: f64,
+}
+
+impl Coated {
+    /// The coat's `roughness` blurs its own reflections, but not the base seen through it. Over a
+    /// specular base, the coat is perfectly smooth.
+    pub fn new(base: Arc<dyn Material>, ir: f64, roughness: f64) -> Self {
+        Self {
+            base,
+            ir,
+            distribution: TrowbridgeReitz::new(roughness),
+            absorption: vector![0.0, 0.0, 0.0],
+            thickness: 0.0,
+        }
+    }
+
+    /// Attenuates light travelling a distance through a coat of the given `thickness` by
+    /// `exp(-absorption * distance)`.
+    pub fn with_absorption(mut self, absorption: Vector3<f64>, thickness: f64) -> Self {
+        self.absorption = absorption;
+        self.thickness = thickness;
+        self
+    }
+
+    /// The fraction of light left after crossing the coat down along `down` and up along `up`,
+    /// given in the local frame.
+    fn transmittance(&self, down: &Vector3<f64>, up: &Vector3<f64>) -> Vector3<f64> {
+        let distance = self.thickness * (1.0 / down.z.abs() + 1.0 / up.z.abs());
+        (-self.absorption * distance).map(f64::exp)
+    }
+
+    /// The base in effect at `rec`, after resolving any materials that choose between others.
+    fn base(&self, rec: &HitRecord) -> &Arc<dyn Material> {
+        let mut base = &self.base;
+        while let Some(chosen) = base.choose(rec) {
+            base = chosen;
+        }
+        base
+    }
+
+    /// The rays that the base sees for light leaving along `wo` that arrived along `wi`, both
+    /// refracted into the coat, with the change in solid angle from `wi` to its refraction.
+    fn base_rays(
+        &self,
+        frame: &Frame,
+        wo: &Vector3<f64>,
+        wi: &Vector3<f64>,
+        rec: &HitRecord,
+        time: f64,
+    ) -> (Ray, Ray, f64) {
+        let normal = vector![0.0, 0.0, 1.0];
+        let down_o = microfacet::refract(wo, &normal, self.ir).unwrap();
+        let down_i = microfacet::refract(wi, &normal, self.ir).unwrap();
+        let base_in = Ray::new(rec.point(), frame.to_world(&down_o), time);
+        let base_out = Ray::new(rec.point(), frame.to_world(&-down_i), time);
+        let solid_angle_ratio = wi.z / (self.ir * self.ir * down_i.z.abs());
+        (base_in, base_out, solid_angle_ratio)
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let frame = Frame::new(&rec.shading_normal());
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let normal = vector![0.0, 0.0, 1.0];
+
+        let coat_probability = fresnel_dielectric(wo.z, self.ir);
+        if random_double() < coat_probability {
+            let (wi, attenuation) = if self.base(rec).is_specular(rec) {
+                (vector![-wo.x, -wo.y, wo.z], 1.0)
+            } else {
+                let wm = self
+                    .distribution
+                    .sample_wm(&wo, random_double(), random_double());
+                let wi = 2.0 * wo.dot(&wm) * wm - wo;
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                let reflectance = fresnel_dielectric(wo.dot(&wm), self.ir);
+                let masking = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+                (wi, reflectance * masking / coat_probability)
+            };
+            let scattered = Ray::new(rec.point(), frame.to_world(&wi), r_in.time);
+            return Some((vector![1.0, 1.0, 1.0] * attenuation, scattered));
+        }
+
+        // Choosing the base with the probability of refracting into the coat cancels that term.
+        let down = microfacet::refract(&wo, &normal, self.ir)?;
+        let base_in = Ray::new(rec.point(), frame.to_world(&down), r_in.time);
+        let (attenuation, base_out) = self.base(rec).scatter(&base_in, rec)?;
+        let up = frame.to_local(&base_out.direction.normalize());
+        if up.z <= 0.0 {
+            return None;
+        }
+        let wi = microfacet::refract(&-up, &-normal, 1.0 / self.ir)?;
+        let exit = 1.0 - fresnel_dielectric(up.z, 1.0 / self.ir);
+
+        let attenuation = attenuation.component_mul(&self.transmittance(&down, &up)) * exit;
+        let scattered = Ray::new(rec.point(), frame.to_world(&wi), r_in.time);
+        Some((attenuation, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        let frame = Frame::new(&rec.shading_normal());
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        let wi = frame.to_local(&scattered.direction.normalize());
+        if self.base(rec).is_specular(rec) || wo.z <= 0.0 || wi.z <= 0.0 {
+            return vector![0.0, 0.0, 0.0];
+        }
+
+        let wm = (wo + wi).normalize();
+        let coat = fresnel_dielectric(wo.dot(&wm), self.ir)
+            * self.distribution.d(&wm)
+            * self.distribution.g(&wo, &wi)
+            / (4.0 * wo.z);
+
+        let (base_in, base_out, solid_angle_ratio) =
+            self.base_rays(&frame, &wo, &wi, rec, r_in.time);
+        let down = frame.to_local(&base_in.direction);
+        let up = frame.to_local(&base_out.direction);
+        let through = (1.0 - fresnel_dielectric(wo.z, self.ir))
+            * (1.0 - fresnel_dielectric(wi.z, self.ir))
+            * solid_angle_ratio;
+        let base = self
+            .base(rec)
+            .eval(&base_in, rec, &base_out)
+            .component_mul(&self.transmittance(&down, &up))
+            * through;
+
+        vector![1.0, 1.0, 1.0] * coat + base
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = Frame::new(&rec.shading_normal());
+        let wo = frame.to_local(&-r_in.direction.normalize());
+        let wi = frame.to_local(&scattered.direction.normalize());
+        if self.base(rec).is_specular(rec) || wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let coat_probability = fresnel_dielectric(wo.z, self.ir);
+        let wm = (wo + wi).normalize();
+        let coat = self.distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm));
+
+        let (base_in, base_out, solid_angle_ratio) =
+            self.base_rays(&frame, &wo, &wi, rec, r_in.time);
+        let base = self.base(rec).scattering_pdf(&base_in, rec, &base_out) * solid_angle_ratio;
+
+        coat_probability * coat + (1.0 - coat_probability) * base
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.base(rec).is_specular(rec)
+    }
+}
+
+/// The average luminance of `texture` over the surface of `shape`, estimated from points sampled
+/// uniformly by area.
+fn average_luminance(texture: &dyn Texture, shape: &dyn Hittable) -> f64 {
+    const N: usize = 16384;
+    let mut sum = 0.0;
+    let mut count = 0;
+    for _ in 0..N {
+        if let Some(rec) = shape.sample_surface(0.0) {
+            sum += luminance(&texture.value_at(&rec));
+            count += 1;
+        }
+    }
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
     }
 }
 
 pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
+    // Set when an `AreaLight` fits the emission to its shape.
+    scale: OnceLock<f64>,
+    two_sided: bool,
+    profile: Option<Arc<IesProfile>>,
+    spectrum: Option<Arc<Spectrum>>,
 }
 
 impl DiffuseLight {
     pub fn new(colour: Vector3<f64>) -> Self {
+        Self::new_from_texture(Arc::new(SolidColour::new(colour)))
+    }
+
+    pub fn new_from_texture(emit: Arc<dyn Texture>) -> Self {
+        Self {
+            emit,
+            scale: OnceLock::new(),
+            two_sided: true,
+            profile: None,
+            spectrum: None,
+        }
+    }
+
+    /// Emits light with a measured spectrum in spectral mode, and its colour otherwise. The
+    /// radiance has a luminance of one until scaled.
+    pub fn new_from_spectrum(spectrum: Arc<Spectrum>) -> Self {
         Self {
-            emit: Arc::new(SolidColour::new(colour)),
+            spectrum: Some(spectrum.clone()),
+            ..Self::new(spectrum.colour())
         }
     }
+
+    /// Emits only from the side that the surface's outward normal points to.
+    pub fn one_sided(mut self) -> Self {
+        self.two_sided = false;
+        self
+    }
+
+    /// Scales the radiance leaving in each direction by a measured profile, with the luminaire
+    /// pointing along the surface normal.
+    pub fn with_profile(mut self, profile: Arc<IesProfile>) -> Self {
+        self.profile = Some(profile);
+        self
+    }
 }
 
 impl Material for DiffuseLight {
@@ -141,8 +1344,47 @@ impl Material for DiffuseLight {
         None
     }
 
-    fn emitted(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
-        self.emit.value(u, v, p)
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vector3<f64> {
+        if !self.two_sided && !rec.front_face() {
+            return vector![0.0, 0.0, 0.0];
+        }
+        let profile = match &self.profile {
+            Some(profile) => profile.value(&-r_in.direction, &rec.normal()),
+            None => 1.0,
+        };
+        let scale = self.scale.get().copied().unwrap_or(1.0);
+        scale * profile * self.emit.value_at(rec)
+    }
+
+    fn fit_emission(&self, shape: &dyn Hittable, emission: Emission) -> Result<(), FitError> {
+        let radiance = match emission {
+            Emission::Radiance(radiance) => radiance,
+            Emission::Power(watts) => {
+                let sides = if self.two_sided { 2.0 } else { 1.0 };
+                watts / (PI * shape.area() * sides)
+            }
+        };
+        let average = average_luminance(&*self.emit, shape);
+        let scale = if average > 0.0 {
+            radiance / average
+        } else {
+            0.0
+        };
+        self.scale.set(scale).map_err(|_| FitError::AlreadyFitted)
+    }
+
+    fn emitted_spectrum(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> Vector4<f64> {
+        let radiance = self.emitted(r_in, rec);
+        match &self.spectrum {
+            // The spectrum's colour has a luminance of one, leaving the scale and profile.
+            Some(spectrum) => wavelengths.evaluate(spectrum) * luminance(&radiance),
+            None => wavelengths.uplift_illuminant(&radiance),
+        }
     }
 }
 
@@ -161,8 +1403,101 @@ impl Isotropic {
 impl Material for Isotropic {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
         Some((
-            self.albedo.value(rec.u(), rec.v(), &rec.point()),
+            self.albedo.value_at(rec),
             Ray::new(rec.point(), random_in_unit_sphere(), r_in.time),
         ))
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        self.albedo.value_at(rec) * self.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::{vector, Vector3};
+
+    use super::{BumpMapped, Lambertian, Material, MixMaterial, NormalMapped};
+    use crate::aarect::XZRect;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::ray::Ray;
+    use crate::texture::Texture;
+
+    /// Rises along u, so that bump mapping with it tilts the normal back against u.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _p: &Vector3<f64>) -> Vector3<f64> {
+            vector![u, u, u]
+        }
+    }
+
+    /// Where a ray meets a unit square lying in the xz plane, whose u runs along x, from above or
+    /// below.
+    fn hit_square(material: Arc<dyn Material>, from_above: bool) -> HitRecord {
+        let square = XZRect::new(0.0, 1.0, 0.0, 1.0, 0.0, material);
+        let r = if from_above {
+            Ray::new(vector![0.3, 1.0, 0.6], vector![0.0, -1.0, 0.0], 0.0)
+        } else {
+            Ray::new(vector![0.3, -1.0, 0.6], vector![0.0, 1.0, 0.0], 0.0)
+        };
+        square.hit(&r, 0.001, f64::INFINITY).unwrap()
+    }
+
+    fn plain() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]))
+    }
+
+    #[test]
+    fn normal_maps_tilt_the_shading_normal_on_either_side() {
+        let flat = Arc::new(NormalMapped::new(plain(), vector![0.5, 0.5, 1.0]));
+        assert_eq!(
+            hit_square(flat, true).shading_normal(),
+            vector![0.0, 1.0, 0.0]
+        );
+
+        // Tilted 45 degrees towards u.
+        let half = 0.5 * 0.5f64.sqrt();
+        let map = vector![0.5 + half, 0.5, 0.5 + half];
+        let tilted: Arc<dyn Material> = Arc::new(NormalMapped::new(plain(), map));
+        let expected = vector![1.0, 1.0, 0.0].normalize();
+        for from_above in [true, false] {
+            let rec = hit_square(tilted.clone(), from_above);
+            let expected = if from_above { expected } else { -expected };
+            assert!((rec.shading_normal() - expected).norm() < 1e-9);
+            assert_eq!(rec.normal().y, if from_above { 1.0 } else { -1.0 });
+        }
+
+        // Materials that choose between others pass on the chosen one's normal.
+        let mix = Arc::new(MixMaterial::new(tilted, plain(), 0.0));
+        assert!((hit_square(mix, true).shading_normal() - expected).norm() < 1e-9);
+    }
+
+    #[test]
+    fn bumps_tilt_the_shading_normal_away_from_the_slope() {
+        let bumped = Arc::new(BumpMapped::new(plain(), Arc::new(Ramp), 0.5));
+        let expected = vector![-0.5, 1.0, 0.0].normalize();
+        let normal = hit_square(bumped, true).shading_normal();
+        assert!((normal - expected).norm() < 1e-6, "{normal}");
+
+        let flat = Arc::new(BumpMapped::new(plain(), vector![0.3, 0.3, 0.3], 0.5));
+        assert_eq!(
+            hit_square(flat, false).shading_normal(),
+            vector![0.0, -1.0, 0.0]
+        );
+    }
 }
@@ -0,0 +1,192 @@
+use std::f64::consts::PI;
+
+use nalgebra::{vector, Vector3};
+
+/// An orthonormal basis around a surface normal, in which the normal is the `z` axis.
+pub struct Frame {
+    s: Vector3<f64>,
+    t: Vector3<f64>,
+    n: Vector3<f64>,
+}
+
+impl Frame {
+    pub fn new(n: &Vector3<f64>) -> Self {
+        let n = n.normalize();
+        let a = if n.x.abs() > 0.9 {
+            vector![0.0, 1.0, 0.0]
+        } else {
+            vector![1.0, 0.0, 0.0]
+        };
+        let s = n.cross(&a).normalize();
+        let t = n.cross(&s);
+        Self { s, t, n }
+    }
+
+    pub fn to_local(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        vector![v.dot(&self.s), v.dot(&self.t), v.dot(&self.n)]
+    }
+
+    pub fn to_world(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        v.x * self.s + v.y * self.t + v.z * self.n
+    }
+}
+
+/// The Trowbridge-Reitz (GGX) distribution of microfacet normals, with Smith's height-correlated
+/// masking-shadowing. Directions are in a local `Frame`, whose `x` and `y` axes the distribution
+/// may be stretched along.
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    /// Maps a perceptually linear `roughness` in `[0, 1]` to the distribution's width.
+    pub fn new(roughness: f64) -> Self {
+        Self::anisotropic(roughness, 0.0)
+    }
+
+    /// Stretches the highlight along the frame's `x` axis as `anisotropy` goes from zero to one,
+    /// following the Disney BRDF's mapping.
+    pub fn anisotropic(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Self {
+            alpha_x: (alpha / aspect).max(1e-4),
+            alpha_y: (alpha * aspect).max(1e-4),
+        }
+    }
+
+    /// Whether the surface is smooth enough to be treated as a perfect mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: &Vector3<f64>) -> f64 {
+        let cos2 = wm.z * wm.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let e = ((wm.x / self.alpha_x).powi(2) + (wm.y / self.alpha_y).powi(2)) / cos2;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2 * cos2 * (1.0 + e).powi(2))
+    }
+
+    fn lambda(&self, w: &Vector3<f64>) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let alpha2_tan2 = ((w.x * self.alpha_x).powi(2) + (w.y * self.alpha_y).powi(2)) / cos2;
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vector3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// The density of sampling `wm` as a normal visible from `wo`.
+    pub fn pdf(&self, wo: &Vector3<f64>, wm: &Vector3<f64>) -> f64 {
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / wo.z.abs() * self.d(wm) * wo.dot(wm).abs()
+    }
+
+    /// Samples a normal visible from `wo`, following Heitz's "Sampling the GGX Distribution of
+    /// Visible Normals".
+    pub fn sample_wm(&self, wo: &Vector3<f64>, u1: f64, u2: f64) -> Vector3<f64> {
+        let wo = if wo.z < 0.0 { -wo } else { *wo };
+        let vh = vector![self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z].normalize();
+
+        let len_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_squared > 0.0 {
+            vector![-vh.y, vh.x, 0.0] / len_squared.sqrt()
+        } else {
+            vector![1.0, 0.0, 0.0]
+        };
+        let t2 = vh.cross(&t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        vector![self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)].normalize()
+    }
+}
+
+/// The fraction of unpolarised light reflected by a conductor with complex index of refraction
+/// `eta + ik`, relative to the outside medium.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+/// The fraction of unpolarised light reflected at a smooth interface, where `eta` is the ratio of
+/// the far side's index of refraction to the incident side's.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let cos_theta_i = cos_theta_i.min(1.0);
+
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// Refracts `wi` through a surface with normal `n` on its side, returning `None` on total
+/// internal reflection.
+pub fn refract(wi: &Vector3<f64>, n: &Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+    let cos_theta_i = n.dot(wi);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+/// The microfacet normal that takes `wo` to `wi` by reflection, or by refraction into a medium
+/// with relative index of refraction `eta`, if it faces `wo`.
+pub fn half_vector(wo: &Vector3<f64>, wi: &Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+    let reflect = wi.z > 0.0;
+    let wm = if reflect { wo + wi } else { wo + eta * wi };
+    if wm.norm_squared() == 0.0 {
+        return None;
+    }
+    let wm = wm.normalize();
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    if wm.dot(wo) <= 0.0 || (!reflect && wm.dot(wi) >= 0.0) {
+        return None;
+    }
+    Some(wm)
+}
@@ -4,6 +4,33 @@ use crate::random::{random_int, random_range_vector3};
 
 const POINT_COUNT: usize = 256;
 
+/// How octaves of noise are summed: `octaves` of them, each `lacunarity` times the frequency and
+/// `gain` times the amplitude of the last.
+#[derive(Clone, Copy)]
+pub struct Fractal {
+    octaves: usize,
+    lacunarity: f64,
+    gain: f64,
+}
+
+impl Fractal {
+    /// Panics if `octaves` is zero, which would leave nothing to normalise the sum by.
+    pub fn new(octaves: usize, lacunarity: f64, gain: f64) -> Self {
+        assert!(octaves > 0, "a fractal needs at least one octave");
+        Self {
+            octaves,
+            lacunarity,
+            gain,
+        }
+    }
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self::new(7, 2.0, 0.5)
+    }
+}
+
 pub struct Perlin {
     ranvec: [Vector3<f64>; POINT_COUNT],
     perm_x: [usize; POINT_COUNT],
@@ -50,18 +77,46 @@ impl Perlin {
     }
 
     pub fn turb(&self, p: &Vector3<f64>) -> f64 {
-        let depth = 7;
-        let mut accum = 0.0;
-        let mut temp_p = *p;
-        let mut weight = 1.0;
+        self.octaves(p, Fractal::default())
+            .map(|(weight, noise)| weight * noise)
+            .sum::<f64>()
+            .abs()
+    }
 
-        for _ in 0..depth {
-            accum += weight * self.noise(&temp_p);
-            weight *= 0.5;
-            temp_p *= 2.0;
-        }
+    /// Fractional Brownian motion: octaves of noise summed and normalised to about `[-1, 1]`.
+    pub fn fbm(&self, p: &Vector3<f64>, fractal: Fractal) -> f64 {
+        let (sum, total) = self
+            .octaves(p, fractal)
+            .fold((0.0, 0.0), |(sum, total), (weight, noise)| {
+                (sum + weight * noise, total + weight)
+            });
+        sum / total
+    }
+
+    /// Ridged multifractal noise in `[0, 1]`, with sharp crests where the noise crosses zero.
+    /// Each octave is weighted by the last, so detail gathers along the ridges.
+    pub fn ridged(&self, p: &Vector3<f64>, fractal: Fractal) -> f64 {
+        let mut ridge_weight = 1.0;
+        let (sum, total) =
+            self.octaves(p, fractal)
+                .fold((0.0, 0.0), |(sum, total), (weight, noise)| {
+                    let ridge = (1.0 - noise.abs()).powi(2) * ridge_weight;
+                    ridge_weight = (2.0 * ridge).clamp(0.0, 1.0);
+                    (sum + weight * ridge, total + weight)
+                });
+        sum / total
+    }
 
-        accum.abs()
+    /// The weight and value of each octave of noise at `p`.
+    fn octaves(&self, p: &Vector3<f64>, fractal: Fractal) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let mut p = *p;
+        let mut weight = 1.0;
+        (0..fractal.octaves).map(move |_| {
+            let octave = (weight, self.noise(&p));
+            weight *= fractal.gain;
+            p *= fractal.lacunarity;
+            octave
+        })
     }
 
     pub fn noise(&self, p: &Vector3<f64>) -> f64 {
@@ -0,0 +1,86 @@
+use std::cmp::Ordering;
+
+use nalgebra::Vector3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Photon {
+    pub position: Vector3<f64>,
+    pub direction: Vector3<f64>,
+    pub power: Vector3<f64>,
+}
+
+/// A kd-tree of photons, stored implicitly by sorting the photons so that every subrange is split
+/// around its middle element.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [usize]) {
+        if photons.len() <= 1 {
+            return;
+        }
+
+        let mut min = photons[0].position;
+        let mut max = photons[0].position;
+        for photon in photons.iter() {
+            min = min.inf(&photon.position);
+            max = max.sup(&photon.position);
+        }
+        let axis = (max - min).imax();
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| {
+            a.position[axis]
+                .partial_cmp(&b.position[axis])
+                .unwrap_or(Ordering::Equal)
+        });
+        axes[mid] = axis;
+
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        Self::build(left, left_axes);
+        Self::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    /// Calls `f` with every photon within `radius` of `p`.
+    pub fn for_each_within<F: FnMut(&Photon)>(&self, p: &Vector3<f64>, radius: f64, mut f: F) {
+        Self::search(&self.photons, &self.axes, p, radius, &mut f);
+    }
+
+    fn search<F: FnMut(&Photon)>(
+        photons: &[Photon],
+        axes: &[usize],
+        p: &Vector3<f64>,
+        radius: f64,
+        f: &mut F,
+    ) {
+        if photons.is_empty() {
+            return;
+        }
+
+        let mid = photons.len() / 2;
+        let photon = &photons[mid];
+        if (photon.position - p).norm_squared() <= radius.powi(2) {
+            f(photon);
+        }
+        if photons.len() == 1 {
+            return;
+        }
+
+        let delta = p[axes[mid]] - photon.position[axes[mid]];
+        if delta <= radius {
+            Self::search(&photons[..mid], &axes[..mid], p, radius, f);
+        }
+        if delta >= -radius {
+            Self::search(&photons[mid + 1..], &axes[mid + 1..], p, radius, f);
+        }
+    }
+}
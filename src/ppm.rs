@@ -0,0 +1,267 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+use rayon::prelude::*;
+
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::light::Light;
+use crate::photon_map::{Photon, PhotonMap};
+use crate::random::{random_double, random_int};
+use crate::ray::Ray;
+use crate::spectrum::SampledWavelengths;
+
+// The fraction of the photons gathered in each pass that a pixel keeps, which controls how
+// quickly its gather radius shrinks.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// A progressive photon mapper. Each pass traces a fresh photon map from the lights and gathers
+/// it at the first non-specular surface seen through each pixel. Every pixel shrinks its own
+/// gather radius as it collects photons, so that its estimate converges.
+pub struct ProgressivePhotonMapper<'a> {
+    world: &'a HittableList,
+    lights: &'a [Arc<dyn Light>],
+    camera: &'a Camera,
+    background: &'a dyn Background,
+    max_depth: i32,
+    photon_count: usize,
+}
+
+/// What one pixel has gathered over the passes so far, after Hachisuka and Jensen's stochastic
+/// progressive photon mapping.
+#[derive(Clone)]
+pub struct PixelEstimate {
+    radius: f64,
+    // The photons kept so far, which may be fractional.
+    photons: f64,
+    // The flux of the photons kept, weighted by the BSDF and the path to the camera.
+    flux: Vector3<f64>,
+    // Light that reached the camera without being gathered, summed over passes.
+    direct: Vector3<f64>,
+}
+
+impl PixelEstimate {
+    pub fn new(radius: f64) -> Self {
+        Self {
+            radius,
+            photons: 0.0,
+            flux: vector![0.0, 0.0, 0.0],
+            direct: vector![0.0, 0.0, 0.0],
+        }
+    }
+
+    /// The radiance through the pixel summed over the passes so far.
+    pub fn total(&self) -> Vector3<f64> {
+        self.direct + self.flux / (PI * self.radius.powi(2))
+    }
+
+    /// Adds `gathered` photons carrying `flux` to the estimate, shrinking the radius so that only
+    /// `ALPHA` of them are kept.
+    fn gather(&mut self, gathered: usize, flux: Vector3<f64>) {
+        if gathered == 0 {
+            return;
+        }
+        let photons = self.photons + ALPHA * gathered as f64;
+        let radius = self.radius * (photons / (self.photons + gathered as f64)).sqrt();
+        self.flux = (self.flux + flux) * (radius / self.radius).powi(2);
+        self.photons = photons;
+        self.radius = radius;
+    }
+}
+
+impl<'a> ProgressivePhotonMapper<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        world: &'a HittableList,
+        lights: &'a [Arc<dyn Light>],
+        camera: &'a Camera,
+        background: &'a dyn Background,
+        max_depth: i32,
+        photon_count: usize,
+    ) -> Self {
+        Self {
+            world,
+            lights,
+            camera,
+            background,
+            max_depth,
+            photon_count,
+        }
+    }
+
+    /// Traces photons from the lights, storing them wherever they land on a non-specular surface
+    /// after at least one bounce. Direct lighting is handled by light sampling instead.
+    pub fn emit_photons(&self) -> PhotonMap {
+        if self.lights.is_empty() {
+            return PhotonMap::new(Vec::new());
+        }
+
+        let photons = (0..self.photon_count)
+            .into_par_iter()
+            .flat_map_iter(|_| self.trace_photon())
+            .collect();
+        PhotonMap::new(photons)
+    }
+
+    fn trace_photon(&self) -> Vec<Photon> {
+        let mut photons = Vec::new();
+
+        let index = random_int(0, self.lights.len() as i32 - 1) as usize;
+        let light_pdf = 1.0 / (self.lights.len() as f64);
+        let time = random_double();
+        let emission = match self.lights[index].sample_le(time) {
+            Some(emission) if emission.pdf_pos > 0.0 && emission.pdf_dir > 0.0 => emission,
+            _ => return photons,
+        };
+
+        let mut power = emission.radiance * emission.normal.dot(&emission.ray.direction).abs()
+            / (light_pdf * emission.pdf_pos * emission.pdf_dir * (self.photon_count as f64));
+        let mut r = emission.ray;
+        r.from_light = true;
+
+        for bounce in 0..self.max_depth {
+            let rec = match self.world.hit(&r, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => break,
+            };
+
+            let material = rec.material();
+            if bounce > 0 && !material.is_specular(&rec) && !material.is_volumetric() {
+                photons.push(Photon {
+                    position: rec.point(),
+                    direction: r.direction.normalize(),
+                    power,
+                });
+            }
+
+            if material.is_dispersive() {
+                power = power.component_mul(&SampledWavelengths::sample().narrow(&mut r));
+            }
+            let (attenuation, mut scattered) = match material.scatter(&r, &rec) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+            scattered.wavelength = r.wavelength;
+            scattered.from_light = true;
+
+            let survival = attenuation.max().min(1.0);
+            if random_double() >= survival {
+                break;
+            }
+            power = power.component_mul(&attenuation) / survival;
+            r = scattered;
+        }
+
+        photons
+    }
+
+    /// Adds a single pass's photon map to the estimate for pixel `(x, y)` of a `width` by
+    /// `height` image.
+    pub fn sample(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        photon_map: &PhotonMap,
+        pixel: &mut PixelEstimate,
+    ) {
+        let direct = self.trace(x, y, width, height, photon_map, pixel);
+        pixel.direct += direct;
+    }
+
+    /// Follows a camera ray through pixel `(x, y)` to where it gathers photons within the pixel's
+    /// radius, returning the light that reached the camera by other means.
+    fn trace(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        photon_map: &PhotonMap,
+        pixel: &mut PixelEstimate,
+    ) -> Vector3<f64> {
+        let s = ((x as f64) + random_double()) / (width as f64);
+        let t = ((y as f64) + random_double()) / (height as f64);
+        let mut r = self.camera.get_ray(s, t);
+
+        let mut colour = vector![0.0, 0.0, 0.0];
+        let mut beta = vector![1.0, 1.0, 1.0];
+
+        for _ in 0..self.max_depth {
+            let rec = match self.world.hit(&r, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => return colour + beta.component_mul(&self.background.value(&r.direction)),
+            };
+
+            let material = rec.material();
+            colour += beta.component_mul(&material.emitted(&r, &rec));
+
+            if !material.is_specular(&rec) && !material.is_volumetric() {
+                let outgoing = Ray::new(rec.point(), -r.direction, r.time);
+                let (mut gathered, mut flux) = (0, vector![0.0, 0.0, 0.0]);
+                photon_map.for_each_within(&rec.point(), pixel.radius, |photon| {
+                    let incoming = Ray::new(photon.position, photon.direction, r.time);
+                    let cosine = rec
+                        .shading_normal()
+                        .dot(&outgoing.direction.normalize())
+                        .abs();
+                    if cosine > 1e-7 {
+                        let f = material.eval(&incoming, &rec, &outgoing) / cosine;
+                        flux += beta.component_mul(&f).component_mul(&photon.power);
+                    }
+                    gathered += 1;
+                });
+                pixel.gather(gathered, flux);
+
+                return colour + beta.component_mul(&self.direct_lighting(&r, &rec));
+            }
+
+            if material.is_dispersive() {
+                beta = beta.component_mul(&SampledWavelengths::sample().narrow(&mut r));
+            }
+            match material.scatter(&r, &rec) {
+                Some((attenuation, mut scattered)) => {
+                    beta = beta.component_mul(&attenuation);
+                    scattered.wavelength = r.wavelength;
+                    r = scattered;
+                }
+                None => return colour,
+            }
+        }
+
+        colour
+    }
+
+    fn direct_lighting(&self, r: &Ray, rec: &HitRecord) -> Vector3<f64> {
+        if self.lights.is_empty() {
+            return vector![0.0, 0.0, 0.0];
+        }
+
+        let index = random_int(0, self.lights.len() as i32 - 1) as usize;
+        let light_pdf = 1.0 / (self.lights.len() as f64);
+        let sample = match self.lights[index].sample_li(&rec.point(), r.time) {
+            Some(sample) if sample.pdf > 0.0 => sample,
+            _ => return vector![0.0, 0.0, 0.0],
+        };
+
+        let to_light = sample.point - rec.point();
+        let distance = to_light.norm();
+        let shadow_ray = Ray::new(rec.point(), to_light / distance, r.time);
+        if self
+            .world
+            .hit(&shadow_ray, 0.001, distance - 0.001)
+            .is_some()
+        {
+            return vector![0.0, 0.0, 0.0];
+        }
+
+        rec.material()
+            .eval(r, rec, &shadow_ray)
+            .component_mul(&sample.radiance)
+            / (sample.pdf * light_pdf)
+    }
+}
@@ -0,0 +1,252 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+
+use crate::perlin::{Fractal, Perlin};
+use crate::texture::Texture;
+use crate::worley::Worley;
+
+/// A gradient map from numbers in `[0, 1]` to colours, blending linearly between stops.
+#[derive(Clone)]
+pub struct ColourRamp {
+    stops: Vec<(f64, Vector3<f64>)>,
+}
+
+impl ColourRamp {
+    /// `stops` pairs positions in `[0, 1]` with the colour there, in any order. Panics if there
+    /// are none.
+    pub fn new(stops: &[(f64, Vector3<f64>)]) -> Self {
+        assert!(!stops.is_empty(), "a colour ramp needs at least one stop");
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+
+    pub fn value(&self, t: f64) -> Vector3<f64> {
+        let after = self.stops.partition_point(|&(position, _)| position <= t);
+        match (after.checked_sub(1), self.stops.get(after)) {
+            (Some(before), Some(&(end, end_colour))) => {
+                let (start, start_colour) = self.stops[before];
+                start_colour.lerp(&end_colour, (t - start) / (end - start))
+            }
+            (Some(before), None) => self.stops[before].1,
+            (None, _) => self.stops[0].1,
+        }
+    }
+}
+
+/// Which fractal sum of noise a `FractalNoise` texture shows.
+#[derive(Clone, Copy)]
+pub enum FractalKind {
+    /// Soft, cloudy fractional Brownian motion.
+    Fbm,
+    /// Sharp crests, like mountain ranges or veins.
+    Ridged,
+}
+
+pub struct FractalNoise {
+    noise: Perlin,
+    kind: FractalKind,
+    scale: f64,
+    fractal: Fractal,
+    ramp: ColourRamp,
+}
+
+impl FractalNoise {
+    /// `scale` is the frequency of the first octave.
+    pub fn new(kind: FractalKind, scale: f64, ramp: ColourRamp) -> Self {
+        Self {
+            noise: Perlin::new(),
+            kind,
+            scale,
+            fractal: Fractal::default(),
+            ramp,
+        }
+    }
+
+    pub fn with_fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+}
+
+impl Texture for FractalNoise {
+    fn value(&self, _u: f64, _v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        let p = self.scale * p;
+        let t = match self.kind {
+            FractalKind::Fbm => 0.5 + 0.5 * self.noise.fbm(&p, self.fractal),
+            FractalKind::Ridged => self.noise.ridged(&p, self.fractal),
+        };
+        self.ramp.value(t)
+    }
+}
+
+/// What a `Voronoi` texture maps through its colour ramp.
+#[derive(Clone, Copy)]
+pub enum VoronoiFeature {
+    /// The distance to the nearest feature point, giving round spots.
+    Distance,
+    /// How far a point is from the border between two cells, giving a network of cracks.
+    Edges,
+    /// A different number for each cell, giving flat cells like crazy paving.
+    Cells,
+}
+
+pub struct Voronoi {
+    noise: Worley,
+    feature: VoronoiFeature,
+    scale: f64,
+    ramp: ColourRamp,
+}
+
+impl Voronoi {
+    /// `scale` is the number of cells per unit length.
+    pub fn new(feature: VoronoiFeature, scale: f64, ramp: ColourRamp) -> Self {
+        Self {
+            noise: Worley::new(),
+            feature,
+            scale,
+            ramp,
+        }
+    }
+}
+
+impl Texture for Voronoi {
+    fn value(&self, _u: f64, _v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        let sample = self.noise.sample(&(self.scale * p));
+        let t = match self.feature {
+            VoronoiFeature::Distance => sample.f1,
+            VoronoiFeature::Edges => sample.f2 - sample.f1,
+            VoronoiFeature::Cells => sample.cell,
+        };
+        self.ramp.value(t)
+    }
+}
+
+/// Growth rings about the y axis, disturbed by noise. The ramp runs across each ring, from the
+/// inside out.
+pub struct Wood {
+    noise: Perlin,
+    rings: f64,
+    turbulence: f64,
+    fractal: Fractal,
+    ramp: ColourRamp,
+}
+
+impl Wood {
+    /// `rings` is the number of rings per unit of radius.
+    pub fn new(rings: f64, ramp: ColourRamp) -> Self {
+        Self {
+            noise: Perlin::new(),
+            rings,
+            turbulence: 0.5,
+            fractal: Fractal::new(4, 2.0, 0.5),
+            ramp,
+        }
+    }
+
+    /// How far, in rings, the noise pushes them about.
+    pub fn with_turbulence(mut self, turbulence: f64) -> Self {
+        self.turbulence = turbulence;
+        self
+    }
+
+    pub fn with_fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f64, _v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        // Stretch the noise along the grain.
+        let grain = vector![p.x, 0.1 * p.y, p.z] * self.rings;
+        let radius =
+            p.xz().norm() * self.rings + self.turbulence * self.noise.fbm(&grain, self.fractal);
+        self.ramp.value(radius - radius.floor())
+    }
+}
+
+/// Veins running across the z axis, folded by turbulence.
+pub struct Marble {
+    noise: Perlin,
+    scale: f64,
+    turbulence: f64,
+    fractal: Fractal,
+    ramp: ColourRamp,
+}
+
+impl Marble {
+    /// `scale` is the number of veins per unit length, before turbulence.
+    pub fn new(scale: f64, ramp: ColourRamp) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            turbulence: 5.0,
+            fractal: Fractal::default(),
+            ramp,
+        }
+    }
+
+    /// How far, in veins, the turbulence folds them.
+    pub fn with_turbulence(mut self, turbulence: f64) -> Self {
+        self.turbulence = turbulence;
+        self
+    }
+
+    pub fn with_fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f64, _v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        let p = self.scale * p;
+        let turbulence = self.noise.fbm(&p, self.fractal).abs();
+        let phase = 2.0 * PI * (p.z + self.turbulence * turbulence);
+        self.ramp.value(0.5 + 0.5 * phase.sin())
+    }
+}
+
+/// Domain warping: looks up another texture at points pushed about by noise, which swirls and
+/// stretches its pattern.
+pub struct Warped {
+    texture: Arc<dyn Texture>,
+    noise: Perlin,
+    scale: f64,
+    strength: f64,
+    fractal: Fractal,
+}
+
+impl Warped {
+    /// Moves points up to about `strength` by noise of frequency `scale`.
+    pub fn new(texture: Arc<dyn Texture>, scale: f64, strength: f64) -> Self {
+        Self {
+            texture,
+            noise: Perlin::new(),
+            scale,
+            strength,
+            fractal: Fractal::new(4, 2.0, 0.5),
+        }
+    }
+
+    pub fn with_fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+}
+
+impl Texture for Warped {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        // Offset lookups into the same noise for each axis, so they vary independently.
+        let q = self.scale * p;
+        let warp = vector![
+            self.noise.fbm(&q, self.fractal),
+            self.noise.fbm(&(q + vector![5.2, 1.3, 2.8]), self.fractal),
+            self.noise.fbm(&(q + vector![1.7, 9.2, 4.1]), self.fractal)
+        ];
+        self.texture.value(u, v, &(p + self.strength * warp))
+    }
+}
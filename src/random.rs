@@ -44,6 +44,34 @@ pub fn random_in_unit_disk() -> Vector3<f64> {
     }
 }
 
+/// A uniformly distributed direction within `acos(cos_theta_max)` of the unit vector `axis`.
+pub fn random_in_cone(axis: &Vector3<f64>, cos_theta_max: f64) -> Vector3<f64> {
+    let cos_theta = 1.0 - random_double() * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * random_double();
+
+    let a = if axis.x.abs() > 0.9 {
+        vector![0.0, 1.0, 0.0]
+    } else {
+        vector![1.0, 0.0, 0.0]
+    };
+    let v1 = axis.cross(&a).normalize();
+    let v2 = axis.cross(&v1);
+    (sin_theta * phi.cos() * v1 + sin_theta * phi.sin() * v2 + cos_theta * axis).normalize()
+}
+
+/// A number in `[0, 1)` that looks random but is always the same for the same point.
+pub fn hash_point(p: &Vector3<f64>) -> f64 {
+    let mut hash: u64 = 0x9e37_79b9_7f4a_7c15;
+    for coordinate in p.iter() {
+        hash ^= coordinate.to_bits();
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+    }
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
 pub fn random_int(min: i32, max: i32) -> i32 {
     rand::thread_rng().gen_range(min..=max)
 }
@@ -1,10 +1,26 @@
 use nalgebra::Vector3;
 
+/// Rays offset from a camera ray by one sample spacing across and down the film, which show how
+/// large an area the ray stands for wherever it lands. Directions are unit length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayDifferentials {
+    pub x_origin: Vector3<f64>,
+    pub x_direction: Vector3<f64>,
+    pub y_origin: Vector3<f64>,
+    pub y_direction: Vector3<f64>,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Ray {
     pub origin: Vector3<f64>,
     pub direction: Vector3<f64>,
     pub time: f64,
+    // The single wavelength in nanometres that the path has been narrowed to by dispersion.
+    pub wavelength: Option<f64>,
+    // Whether the path was traced from a light, carrying importance rather than radiance.
+    pub from_light: bool,
+    // Only camera rays and their specular bounces have differentials.
+    pub differentials: Option<RayDifferentials>,
 }
 
 impl Ray {
@@ -13,10 +29,69 @@ impl Ray {
             origin,
             direction,
             time,
+            wavelength: None,
+            from_light: false,
+            differentials: None,
         }
     }
 
     pub fn at(&self, t: f64) -> Vector3<f64> {
         self.origin + t * self.direction
     }
+
+    /// Moves the ray's origins with `point` and its directions with `direction`, keeping its time,
+    /// wavelength and whether it came from a light.
+    pub fn transformed(
+        &self,
+        point: impl Fn(&Vector3<f64>) -> Vector3<f64>,
+        direction: impl Fn(&Vector3<f64>) -> Vector3<f64>,
+    ) -> Self {
+        Self {
+            origin: point(&self.origin),
+            direction: direction(&self.direction),
+            differentials: self.differentials.map(|d| RayDifferentials {
+                x_origin: point(&d.x_origin),
+                x_direction: direction(&d.x_direction),
+                y_origin: point(&d.y_origin),
+                y_direction: direction(&d.y_direction),
+            }),
+            ..*self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{vector, Vector3};
+
+    use super::{Ray, RayDifferentials};
+
+    #[test]
+    fn transformed_rays_keep_their_differentials_time_and_wavelength() {
+        let mut r = Ray::new(vector![1.0, 2.0, 3.0], vector![0.0, 0.0, -1.0], 0.25);
+        r.wavelength = Some(550.0);
+        r.from_light = true;
+        r.differentials = Some(RayDifferentials {
+            x_origin: vector![1.0, 2.0, 3.0],
+            x_direction: vector![0.1, 0.0, -1.0],
+            y_origin: vector![1.0, 2.5, 3.0],
+            y_direction: vector![0.0, 0.1, -1.0],
+        });
+
+        // A quarter turn about y, then a step along x.
+        let turn = |v: &Vector3<f64>| vector![v.z, v.y, -v.x];
+        let moved = r.transformed(|p| turn(p) + vector![1.0, 0.0, 0.0], turn);
+
+        assert_eq!(moved.origin, vector![4.0, 2.0, -1.0]);
+        assert_eq!(moved.direction, vector![-1.0, 0.0, 0.0]);
+        assert_eq!(
+            (moved.time, moved.wavelength, moved.from_light),
+            (0.25, Some(550.0), true)
+        );
+        let differentials = moved.differentials.unwrap();
+        assert_eq!(differentials.x_origin, vector![4.0, 2.0, -1.0]);
+        assert_eq!(differentials.x_direction, vector![-1.0, 0.0, -0.1]);
+        assert_eq!(differentials.y_origin, vector![4.0, 2.5, -1.0]);
+        assert_eq!(differentials.y_direction, vector![-1.0, 0.1, 0.0]);
+    }
 }
@@ -1,14 +1,30 @@
 use crate::aabox::AaBox;
 use crate::aarect::{XYRect, XZRect, YZRect};
+use crate::asset::AssetError;
 use crate::bvh::BvhNode;
 use crate::constant_medium::ConstantMedium;
-use crate::hittable::{RotateY, Translate};
+use crate::hittable::{AlphaMasked, Hittable, RotateY, Translate};
 use crate::hittable_list::HittableList;
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::ies::IesProfile;
+use crate::light::{AreaLight, DirectionalLight, Light, PointLight, SpotLight};
+use crate::material::{
+    BumpMapped, Coated, Conductor, Dielectric, DiffuseLight, Lambertian, Material, Metal,
+    MixMaterial, NormalMapped, OrenNayar, Principled, RoughDielectric, Translucent,
+};
 use crate::moving_sphere::MovingSphere;
+use crate::perlin::Fractal;
+use crate::procedural::{
+    ColourRamp, FractalKind, FractalNoise, Marble, Voronoi, VoronoiFeature, Warped, Wood,
+};
 use crate::random::{random_double, random_range_double, random_range_vector3, random_vector3};
+use crate::spectrum::{Dispersion, Spectrum};
 use crate::sphere::Sphere;
-use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture};
+use crate::subsurface::Subsurface;
+use crate::texture::{
+    CheckerPattern, CheckerTexture, Filter, ImageTexture, NoiseTexture, Texture, WrapMode,
+};
+use crate::texture_graph::TextureGraph;
+use crate::triangle::Triangle;
 use nalgebra::vector;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -16,10 +32,10 @@ use std::sync::Arc;
 pub fn random_scene() -> HittableList {
     let mut world = HittableList::default();
 
-    let checker = Arc::new(CheckerTexture::new(
-        vector![0.2, 0.3, 0.1],
-        vector![0.9, 0.9, 0.9],
-    ));
+    let checker = Arc::new(
+        CheckerTexture::new(vector![0.2, 0.3, 0.1], vector![0.9, 0.9, 0.9])
+            .with_pattern(CheckerPattern::Solid { cell_size: 0.3 }),
+    );
     let material_ground = Arc::new(Lambertian::new_from_texture(checker));
     world.add(Arc::new(Sphere::new(
         vector![0.0, -1000.0, -1.0],
@@ -88,10 +104,14 @@ pub fn random_scene() -> HittableList {
 pub fn two_spheres() -> HittableList {
     let mut objects = HittableList::default();
 
-    let checker = Arc::new(CheckerTexture::new(
-        vector![0.2, 0.3, 0.1],
-        vector![0.9, 0.9, 0.9],
-    ));
+    let checker = Arc::new(
+        CheckerTexture::new(vector![0.2, 0.3, 0.1], vector![0.9, 0.9, 0.9]).with_pattern(
+            CheckerPattern::Uv {
+                u_repeats: 16.0,
+                v_repeats: 8.0,
+            },
+        ),
+    );
 
     objects.add(Arc::new(Sphere::new(
         vector![0.0, -10.0, 0.0],
@@ -122,15 +142,15 @@ pub fn two_perlin_spheres() -> HittableList {
     objects
 }
 
-pub fn earth() -> HittableList {
-    let earth_texture = Arc::new(ImageTexture::new(PathBuf::from("earthmap.jpg")));
+pub fn earth() -> Result<HittableList, AssetError> {
+    let earth_texture = Arc::new(ImageTexture::new(PathBuf::from("earthmap.jpg"))?);
     let earth_surface = Arc::new(Lambertian::new_from_texture(earth_texture));
     let globe = Arc::new(Sphere::new(vector![0.0, 0.0, 0.0], 2.0, earth_surface));
 
-    HittableList::new(globe)
+    Ok(HittableList::new(globe))
 }
 
-pub fn simple_light() -> HittableList {
+pub fn simple_light() -> (HittableList, Vec<Arc<dyn Light>>) {
     let mut objects = HittableList::default();
 
     let pertext = Arc::new(NoiseTexture::new(4.0));
@@ -143,12 +163,750 @@ pub fn simple_light() -> HittableList {
     objects.add(Arc::new(Sphere::new(vector![0.0, 2.0, 0.0], 2.0, permat)));
 
     let difflight = Arc::new(DiffuseLight::new(vector![4.0, 4.0, 4.0]));
-    objects.add(Arc::new(XYRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight)));
+    let light = Arc::new(XYRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight));
+    objects.add(light.clone());
+
+    (objects, vec![Arc::new(AreaLight::new(light))])
+}
+
+pub fn delta_lights() -> (HittableList, Vec<Arc<dyn Light>>) {
+    let mut objects = HittableList::default();
+
+    let ground = Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        ground,
+    )));
+
+    let diffuse = Arc::new(Lambertian::new(vector![0.4, 0.2, 0.1]));
+    objects.add(Arc::new(Sphere::new(vector![-4.0, 1.0, 0.0], 1.0, diffuse)));
+    let glass = Arc::new(Dielectric::new(1.5));
+    objects.add(Arc::new(Sphere::new(vector![0.0, 1.0, 0.0], 1.0, glass)));
+    let metal = Arc::new(Metal::new(vector![0.7, 0.6, 0.5], 0.0));
+    objects.add(Arc::new(Sphere::new(vector![4.0, 1.0, 0.0], 1.0, metal)));
+
+    let world_bounds = objects.bounding_box(0.0, 1.0).unwrap();
+    let lights: Vec<Arc<dyn Light>> = vec![
+        Arc::new(PointLight::new(
+            vector![-4.0, 4.0, 2.0],
+            vector![30.0, 25.0, 20.0],
+        )),
+        Arc::new(SpotLight::new(
+            vector![4.0, 6.0, 3.0],
+            vector![4.0, 0.0, 0.0],
+            vector![120.0, 120.0, 120.0],
+            25.0,
+            15.0,
+        )),
+        Arc::new(DirectionalLight::new(
+            vector![-1.0, 2.0, -1.0],
+            vector![0.1, 0.15, 0.3],
+            world_bounds,
+        )),
+    ];
+
+    (objects, lights)
+}
+
+pub fn textured_lights() -> Result<(HittableList, Vec<Arc<dyn Light>>), AssetError> {
+    let mut objects = HittableList::default();
+
+    let ground = Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        ground,
+    )));
+    let white = Arc::new(Lambertian::new(vector![0.73, 0.73, 0.73]));
+    objects.add(Arc::new(Sphere::new(vector![0.0, 2.0, 0.0], 2.0, white)));
+
+    let earth_texture = Arc::new(ImageTexture::new(PathBuf::from("earthmap.jpg"))?);
+    let panel_light = Arc::new(DiffuseLight::new_from_texture(earth_texture).one_sided());
+    let panel = Arc::new(XYRect::new(-3.0, 3.0, 1.0, 5.0, -4.0, panel_light));
+    objects.add(panel.clone());
+
+    let noise_light = Arc::new(DiffuseLight::new_from_texture(Arc::new(NoiseTexture::new(
+        4.0,
+    ))));
+    let globe = Arc::new(Sphere::new(vector![4.0, 1.0, 3.0], 1.0, noise_light));
+    objects.add(globe.clone());
+
+    Ok((
+        objects,
+        vec![
+            Arc::new(AreaLight::new(panel).with_power(250.0).unwrap()),
+            Arc::new(AreaLight::new(globe).with_radiance(4.0).unwrap()),
+        ],
+    ))
+}
+
+pub fn ies_wall() -> Result<(HittableList, Vec<Arc<dyn Light>>), AssetError> {
+    let mut objects = HittableList::default();
+
+    let white = Arc::new(Lambertian::new(vector![0.73, 0.73, 0.73]));
+    objects.add(Arc::new(XYRect::new(
+        -8.0,
+        8.0,
+        0.0,
+        7.0,
+        -1.0,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XZRect::new(-8.0, 8.0, -1.0, 8.0, 0.0, white)));
+
+    let profile = Arc::new(IesProfile::new(PathBuf::from("downlight.ies"))?);
+
+    let panel_light =
+        Arc::new(DiffuseLight::new(vector![1.0, 1.0, 1.0]).with_profile(profile.clone()));
+    let panel = Arc::new(XZRect::new(5.75, 6.25, -0.75, -0.25, 6.0, panel_light));
+    objects.add(panel.clone());
+
+    let lights: Vec<Arc<dyn Light>> = vec![
+        Arc::new(
+            PointLight::new(vector![-6.0, 6.0, -0.5], vector![40.0, 36.0, 30.0])
+                .with_profile(profile.clone()),
+        ),
+        Arc::new(
+            PointLight::new(vector![-2.0, 6.0, -0.5], vector![40.0, 36.0, 30.0])
+                .with_profile(profile.clone()),
+        ),
+        Arc::new(
+            SpotLight::new(
+                vector![2.0, 6.0, -0.5],
+                vector![2.0, 0.0, -0.5],
+                vector![40.0, 36.0, 30.0],
+                60.0,
+                50.0,
+            )
+            .with_profile(profile),
+        ),
+        Arc::new(AreaLight::new(panel).with_power(60.0).unwrap()),
+    ];
+
+    Ok((objects, lights))
+}
+
+pub fn materials() -> HittableList {
+    let mut objects = HittableList::default();
+
+    let checker = Arc::new(CheckerTexture::new(
+        vector![0.2, 0.3, 0.1],
+        vector![0.9, 0.9, 0.9],
+    ));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+
+    let conductors = [
+        Conductor::gold(0.0),
+        Conductor::gold(0.3),
+        Conductor::copper(0.2),
+        Conductor::aluminium(0.5),
+    ];
+    for (i, conductor) in conductors.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            Arc::new(conductor),
+        )));
+    }
+
+    let frosted = Arc::new(RoughDielectric::new(1.5, 0.3));
+    objects.add(Arc::new(Sphere::new(
+        vector![-3.0, 1.0, -3.0],
+        1.0,
+        frosted,
+    )));
+    let etched = Arc::new(RoughDielectric::new_from_texture(
+        1.5,
+        Arc::new(NoiseTexture::new(4.0)),
+    ));
+    objects.add(Arc::new(Sphere::new(vector![0.0, 1.0, -3.0], 1.0, etched)));
+    let tinted = Arc::new(Dielectric::new(1.5).with_transmittance(vector![0.2, 0.6, 0.9], 1.0));
+    objects.add(Arc::new(Sphere::new(vector![3.0, 1.0, -3.0], 1.0, tinted)));
+
+    let flint = Arc::new(Dielectric::new(1.5).with_dispersion(Dispersion::DENSE_FLINT));
+    objects.add(Arc::new(Sphere::new(vector![-1.5, 0.7, 3.0], 0.7, flint)));
+    let diamond = Arc::new(Dielectric::new(2.4).with_dispersion(Dispersion::Cauchy {
+        a: 2.379,
+        b: 0.0133,
+    }));
+    objects.add(Arc::new(Sphere::new(vector![1.5, 0.7, 3.0], 0.7, diamond)));
+
+    objects
+}
+
+pub fn principled() -> HittableList {
+    let mut objects = HittableList::default();
+
+    let checker = Arc::new(CheckerTexture::new(
+        vector![0.2, 0.3, 0.1],
+        vector![0.9, 0.9, 0.9],
+    ));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+
+    let front = [
+        Principled::new(vector![0.8, 0.1, 0.1]).with_roughness(0.3),
+        Principled::new(vector![0.9, 0.9, 0.9])
+            .with_metallic(1.0)
+            .with_roughness(0.4)
+            .with_anisotropy(0.8),
+        Principled::new(vector![0.05, 0.1, 0.5])
+            .with_metallic(0.5)
+            .with_roughness(0.5)
+            .with_clearcoat(1.0)
+            .with_clearcoat_roughness(0.05),
+        Principled::new(vector![0.3, 0.05, 0.2])
+            .with_roughness(1.0)
+            .with_sheen(1.0)
+            .with_sheen_tint(0.8),
+    ];
+    let back = [
+        Principled::new(vector![0.8, 1.0, 0.9])
+            .with_transmission(1.0)
+            .with_roughness(0.05),
+        Principled::new(vector![0.9, 0.6, 0.2])
+            .with_metallic(1.0)
+            .with_roughness(Arc::new(NoiseTexture::new(4.0))),
+        Principled::new(vector![0.7, 0.7, 0.7])
+            .with_specular(1.0)
+            .with_specular_tint(1.0)
+            .with_ior(1.8),
+    ];
+    for (i, material) in front.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            Arc::new(material),
+        )));
+    }
+    for (i, material) in back.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-3.0 + 3.0 * (i as f64), 1.0, -3.0],
+            1.0,
+            Arc::new(material),
+        )));
+    }
+
+    objects
+}
+
+pub fn layered() -> HittableList {
+    let mut objects = HittableList::default();
+
+    let checker = Arc::new(CheckerTexture::new(
+        vector![0.2, 0.3, 0.1],
+        vector![0.9, 0.9, 0.9],
+    ));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+
+    let car_paint = Coated::new(
+        Arc::new(
+            Principled::new(vector![0.5, 0.02, 0.03])
+                .with_metallic(0.6)
+                .with_roughness(0.4),
+        ),
+        1.5,
+        0.02,
+    );
+    let wood = MixMaterial::new(
+        Arc::new(Lambertian::new(vector![0.55, 0.33, 0.16])),
+        Arc::new(Lambertian::new(vector![0.3, 0.15, 0.06])),
+        Arc::new(NoiseTexture::new(6.0)),
+    );
+    let varnished_wood =
+        Coated::new(Arc::new(wood), 1.55, 0.1).with_absorption(vector![0.2, 0.6, 1.5], 0.3);
+    let tarnished_copper = MixMaterial::new(
+        Arc::new(Conductor::copper(0.2)),
+        Arc::new(Lambertian::new(vector![0.2, 0.45, 0.35])),
+        Arc::new(NoiseTexture::new(3.0)),
+    );
+    let lacquered_gold = Coated::new(Arc::new(Conductor::gold(0.0)), 1.5, 0.0)
+        .with_absorption(vector![0.0, 0.3, 0.9], 0.2);
+
+    let materials: [Arc<dyn Material>; 4] = [
+        Arc::new(car_paint),
+        Arc::new(varnished_wood),
+        Arc::new(tarnished_copper),
+        Arc::new(lacquered_gold),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            material,
+        )));
+    }
 
     objects
 }
 
-pub fn cornell_box() -> HittableList {
+pub fn diffuse() -> HittableList {
+    let mut objects = HittableList::default();
+
+    let ground = OrenNayar::new(vector![0.45, 0.42, 0.38], 0.5);
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        Arc::new(ground),
+    )));
+
+    let clay = vector![0.7, 0.35, 0.2];
+    let spheres: [Arc<dyn Material>; 4] = [
+        Arc::new(Lambertian::new(clay)),
+        Arc::new(OrenNayar::new(clay, 0.5)),
+        Arc::new(OrenNayar::new(clay, 1.2)),
+        Arc::new(OrenNayar::new(
+            vector![0.6, 0.6, 0.6],
+            Arc::new(NoiseTexture::new(4.0)),
+        )),
+    ];
+    for (i, material) in spheres.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            material,
+        )));
+    }
+
+    // Leaves and paper standing in front of the sky, lit mostly from behind.
+    let leaf = Arc::new(Translucent::new(
+        vector![0.08, 0.2, 0.04],
+        vector![0.2, 0.45, 0.05],
+    ));
+    let paper = Arc::new(Translucent::new(
+        vector![0.6, 0.6, 0.55],
+        vector![0.3, 0.3, 0.25],
+    ));
+    objects.add(Arc::new(XYRect::new(-5.0, -0.5, 0.0, 3.0, -3.0, leaf)));
+    objects.add(Arc::new(XYRect::new(0.5, 5.0, 0.0, 3.0, -3.0, paper)));
+
+    objects
+}
+
+pub fn subsurface() -> HittableList {
+    let mut objects = HittableList::default();
+
+    let checker = Arc::new(CheckerTexture::new(
+        vector![0.2, 0.3, 0.1],
+        vector![0.9, 0.9, 0.9],
+    ));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        Arc::new(Lambertian::new_from_texture(checker)),
+    )));
+
+    // Marble, skin, wax and milk. Red light travels furthest in skin and wax before scattering.
+    let media: [(Arc<dyn Material>, _, _); 4] = [
+        (
+            Arc::new(Dielectric::new(1.5)),
+            vector![0.83, 0.79, 0.75],
+            vector![0.3, 0.3, 0.28],
+        ),
+        (
+            Arc::new(RoughDielectric::new(1.4, 0.3)),
+            vector![0.8, 0.5, 0.4],
+            vector![0.2, 0.08, 0.05],
+        ),
+        (
+            Arc::new(RoughDielectric::new(1.45, 0.1)),
+            vector![0.9, 0.8, 0.5],
+            vector![0.3, 0.2, 0.12],
+        ),
+        (
+            Arc::new(Dielectric::new(1.35)),
+            vector![0.95, 0.95, 0.93],
+            vector![0.05, 0.05, 0.05],
+        ),
+    ];
+    for (i, (interface, albedo, mean_free_path)) in media.into_iter().enumerate() {
+        let boundary = Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            interface,
+        ));
+        objects.add(Arc::new(Subsurface::new(boundary, albedo, mean_free_path)));
+    }
+
+    objects
+}
+
+pub fn bumps() -> Result<(HittableList, Vec<Arc<dyn Light>>), AssetError> {
+    let mut objects = HittableList::default();
+
+    let tiles = Arc::new(
+        ImageTexture::new(PathBuf::from("tiles_normal.png"))?
+            .with_srgb(false)
+            .with_scale(4.0, 4.0),
+    );
+    let floor = NormalMapped::new(
+        Arc::new(Lambertian::new(vector![0.6, 0.55, 0.5])),
+        tiles.clone(),
+    );
+    objects.add(Arc::new(XZRect::new(
+        -8.0,
+        8.0,
+        -8.0,
+        8.0,
+        0.0,
+        Arc::new(floor),
+    )));
+
+    let spheres: [Arc<dyn Material>; 4] = [
+        Arc::new(Lambertian::new(vector![0.7, 0.35, 0.2])),
+        Arc::new(BumpMapped::new(
+            Arc::new(Lambertian::new(vector![0.7, 0.35, 0.2])),
+            Arc::new(NoiseTexture::new(4.0)),
+            0.04,
+        )),
+        Arc::new(BumpMapped::new(
+            Arc::new(Conductor::aluminium(0.1)),
+            Arc::new(NoiseTexture::new(8.0)),
+            0.02,
+        )),
+        Arc::new(NormalMapped::new(
+            Arc::new(Conductor::gold(0.2)),
+            tiles.clone(),
+        )),
+    ];
+    for (i, material) in spheres.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            material,
+        )));
+    }
+
+    // A tiled pyramid behind them, whose faces take their tangents from their texture coordinates.
+    let stone = Arc::new(NormalMapped::new(
+        Arc::new(Lambertian::new(vector![0.5, 0.5, 0.55])),
+        tiles,
+    ));
+    let apex = vector![0.0, 3.0, -5.0];
+    let base = [
+        vector![-2.5, 0.0, -2.5],
+        vector![2.5, 0.0, -2.5],
+        vector![2.5, 0.0, -7.5],
+        vector![-2.5, 0.0, -7.5],
+    ];
+    for i in 0..4 {
+        objects.add(Arc::new(
+            Triangle::new(base[i], base[(i + 1) % 4], apex, stone.clone()).with_uvs(
+                vector![0.0, 0.0],
+                vector![1.0, 0.0],
+                vector![0.5, 1.0],
+            ),
+        ));
+    }
+
+    // A low sun, to pick out the relief.
+    let world_bounds = objects.bounding_box(0.0, 1.0).unwrap();
+    let lights: Vec<Arc<dyn Light>> = vec![Arc::new(DirectionalLight::new(
+        vector![-1.0, 0.5, 0.3],
+        vector![3.0, 2.8, 2.5],
+        world_bounds,
+    ))];
+
+    Ok((objects, lights))
+}
+
+pub fn cutouts() -> Result<(HittableList, Vec<Arc<dyn Light>>), AssetError> {
+    let mut objects = HittableList::default();
+
+    let ground = Arc::new(Lambertian::new(vector![0.45, 0.42, 0.38]));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        ground,
+    )));
+
+    // A fence with square holes, masked by the luminance of a checker.
+    let holes = Arc::new(CheckerTexture::new(
+        vector![1.0, 1.0, 1.0],
+        vector![0.0, 0.0, 0.0],
+    ));
+    let fence = Arc::new(XYRect::new(
+        -6.0,
+        6.0,
+        0.0,
+        2.5,
+        -2.0,
+        Arc::new(Lambertian::new(vector![0.5, 0.35, 0.2])),
+    ));
+    objects.add(Arc::new(AlphaMasked::new(fence, holes)));
+
+    // Leaves cut out of quads by the alpha channel of their image.
+    let leaf = Arc::new(ImageTexture::new(PathBuf::from("leaf.png"))?.with_wrap(WrapMode::Clamp));
+    let placements = [
+        (vector![-4.0, 0.0, 1.0], 20.0),
+        (vector![-1.5, 0.2, 0.0], -35.0),
+        (vector![1.0, 0.0, 1.5], 60.0),
+        (vector![3.5, 0.1, 0.5], -10.0),
+    ];
+    for (offset, angle) in placements {
+        let quad = Arc::new(XYRect::new(
+            -0.75,
+            0.75,
+            0.0,
+            3.0,
+            0.0,
+            Arc::new(Translucent::new(leaf.clone(), leaf.clone())),
+        ));
+        let masked = Arc::new(AlphaMasked::new(quad, leaf.clone()));
+        objects.add(Arc::new(Translate::new(
+            Arc::new(RotateY::new(masked, angle)),
+            offset,
+        )));
+    }
+
+    let world_bounds = objects.bounding_box(0.0, 1.0).unwrap();
+    let lights: Vec<Arc<dyn Light>> = vec![Arc::new(DirectionalLight::new(
+        vector![0.5, 1.0, -1.0],
+        vector![3.0, 2.8, 2.5],
+        world_bounds,
+    ))];
+
+    Ok((objects, lights))
+}
+
+pub fn filtering() -> Result<HittableList, AssetError> {
+    let mut objects = HittableList::default();
+
+    let earth = ImageTexture::new(PathBuf::from("earthmap.jpg"))?;
+    let textures = [
+        // A close-up of Europe under each filter.
+        earth
+            .clone()
+            .with_filter(Filter::Nearest)
+            .with_scale(0.04, 0.04)
+            .with_offset(0.49, 0.74),
+        earth.clone().with_scale(0.04, 0.04).with_offset(0.49, 0.74),
+        earth
+            .clone()
+            .with_filter(Filter::Bicubic)
+            .with_scale(0.04, 0.04)
+            .with_offset(0.49, 0.74),
+        // The whole map twice over, under each wrap mode.
+        earth.clone().with_scale(2.0, 2.0).with_rotation(30.0),
+        earth
+            .clone()
+            .with_wrap(WrapMode::Mirror)
+            .with_scale(2.0, 2.0),
+        earth
+            .clone()
+            .with_wrap(WrapMode::Clamp)
+            .with_scale(2.0, 2.0),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        let x = -3.0 + 3.0 * (i % 3) as f64;
+        let y = if i < 3 { 2.2 } else { 0.0 };
+        objects.add(Arc::new(XYRect::new(
+            x - 1.4,
+            x + 1.4,
+            y,
+            y + 2.0,
+            0.0,
+            Arc::new(DiffuseLight::new_from_texture(Arc::new(texture))),
+        )));
+    }
+
+    Ok(objects)
+}
+
+pub fn procedural() -> HittableList {
+    let mut objects = HittableList::default();
+
+    let ground = Arc::new(Lambertian::new(vector![0.45, 0.42, 0.38]));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        ground,
+    )));
+
+    let sky = ColourRamp::new(&[
+        (0.35, vector![0.1, 0.25, 0.6]),
+        (0.65, vector![0.9, 0.9, 0.95]),
+    ]);
+    let veins = ColourRamp::new(&[
+        (0.0, vector![0.05, 0.05, 0.05]),
+        (0.8, vector![0.1, 0.08, 0.05]),
+        (1.0, vector![0.9, 0.7, 0.2]),
+    ]);
+    let spots = ColourRamp::new(&[
+        (0.0, vector![0.9, 0.6, 0.1]),
+        (0.4, vector![0.9, 0.6, 0.1]),
+        (0.5, vector![0.15, 0.1, 0.05]),
+    ]);
+    let cracks = ColourRamp::new(&[
+        (0.0, vector![0.1, 0.08, 0.06]),
+        (0.06, vector![0.1, 0.08, 0.06]),
+        (0.1, vector![0.6, 0.55, 0.45]),
+    ]);
+    let paving = ColourRamp::new(&[
+        (0.0, vector![0.5, 0.2, 0.15]),
+        (0.5, vector![0.6, 0.55, 0.4]),
+        (1.0, vector![0.3, 0.35, 0.4]),
+    ]);
+    let wood = ColourRamp::new(&[
+        (0.0, vector![0.55, 0.35, 0.18]),
+        (0.7, vector![0.45, 0.27, 0.12]),
+        (1.0, vector![0.25, 0.13, 0.05]),
+    ]);
+    let marble = ColourRamp::new(&[
+        (0.0, vector![0.2, 0.22, 0.25]),
+        (0.3, vector![0.8, 0.8, 0.78]),
+        (1.0, vector![0.9, 0.9, 0.88]),
+    ]);
+    let lava = ColourRamp::new(&[
+        (0.3, vector![0.05, 0.0, 0.0]),
+        (0.5, vector![0.7, 0.1, 0.0]),
+        (0.7, vector![1.0, 0.8, 0.2]),
+    ]);
+
+    let textures: [Arc<dyn Texture>; 8] = [
+        Arc::new(FractalNoise::new(FractalKind::Fbm, 2.0, sky)),
+        Arc::new(
+            FractalNoise::new(FractalKind::Ridged, 1.5, veins)
+                .with_fractal(Fractal::new(6, 2.2, 0.6)),
+        ),
+        Arc::new(Voronoi::new(VoronoiFeature::Distance, 3.0, spots)),
+        Arc::new(Voronoi::new(VoronoiFeature::Edges, 3.0, cracks)),
+        Arc::new(Voronoi::new(VoronoiFeature::Cells, 3.0, paving)),
+        Arc::new(
+            Wood::new(6.0, wood)
+                .with_turbulence(0.7)
+                .with_fractal(Fractal::new(3, 2.0, 0.5)),
+        ),
+        Arc::new(
+            Marble::new(0.8, marble)
+                .with_turbulence(4.0)
+                .with_fractal(Fractal::new(5, 2.0, 0.5)),
+        ),
+        Arc::new(
+            Warped::new(
+                Arc::new(FractalNoise::new(FractalKind::Fbm, 1.5, lava)),
+                1.0,
+                1.5,
+            )
+            .with_fractal(Fractal::new(5, 2.0, 0.5)),
+        ),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        let (x, z) = (-4.5 + 3.0 * (i % 4) as f64, if i < 4 { -2.5 } else { 2.0 });
+        objects.add(Arc::new(Sphere::new(
+            vector![x, 1.0, z],
+            1.0,
+            Arc::new(Lambertian::new_from_texture(texture)),
+        )));
+    }
+
+    objects
+}
+
+pub fn texture_graph() -> Result<HittableList, AssetError> {
+    let mut objects = HittableList::default();
+
+    let ground = Arc::new(Lambertian::new(vector![0.45, 0.42, 0.38]));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        ground,
+    )));
+
+    // The textures are built from nodes described in a file, rather than in Rust.
+    let graph = TextureGraph::new(PathBuf::from("texture_graph.tex"))?;
+    let names = [
+        "rust",
+        "glow",
+        "tinted",
+        "heat",
+        "inlay",
+        "grain",
+        "boxed_earth",
+        "stone",
+    ];
+    let textures = names
+        .map(|name| graph.texture(name))
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    for (i, texture) in textures.into_iter().enumerate() {
+        let (x, z) = (-4.5 + 3.0 * (i % 4) as f64, if i < 4 { -2.5 } else { 2.0 });
+        let material = Arc::new(Lambertian::new_from_texture(texture));
+        if i < 6 {
+            objects.add(Arc::new(Sphere::new(vector![x, 1.0, z], 1.0, material)));
+        } else {
+            // Turned boxes, whose textures stay put on their faces.
+            let block = Arc::new(AaBox::new(
+                vector![-0.8, 0.0, -0.8],
+                vector![0.8, 1.6, 0.8],
+                material,
+            ));
+            let block = Arc::new(RotateY::new(block, 30.0));
+            objects.add(Arc::new(Translate::new(block, vector![x, 0.0, z])));
+        }
+    }
+
+    Ok(objects)
+}
+
+pub fn illuminants() -> (HittableList, Vec<Arc<dyn Light>>) {
+    let mut objects = HittableList::default();
+
+    let ground = Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]));
+    objects.add(Arc::new(Sphere::new(
+        vector![0.0, -1000.0, 0.0],
+        1000.0,
+        ground,
+    )));
+
+    let albedos = [
+        vector![0.65, 0.05, 0.05],
+        vector![0.12, 0.45, 0.15],
+        vector![0.73, 0.73, 0.73],
+        vector![0.1, 0.2, 0.6],
+    ];
+    for (i, albedo) in albedos.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            vector![-4.5 + 3.0 * (i as f64), 1.0, 0.0],
+            1.0,
+            Arc::new(Lambertian::new(albedo)),
+        )));
+    }
+    let prism = Arc::new(Dielectric::new(1.5).with_dispersion(Dispersion::DENSE_FLINT));
+    objects.add(Arc::new(Sphere::new(vector![0.0, 0.7, 3.0], 0.7, prism)));
+
+    // Daylight on the left and a tungsten lamp on the right.
+    let daylight = Arc::new(DiffuseLight::new_from_spectrum(Arc::new(Spectrum::d65())));
+    let tungsten = Arc::new(DiffuseLight::new_from_spectrum(Arc::new(
+        Spectrum::blackbody(2856.0),
+    )));
+    let left = Arc::new(XZRect::new(-5.0, -3.0, -1.0, 1.0, 5.0, daylight));
+    let right = Arc::new(XZRect::new(3.0, 5.0, -1.0, 1.0, 5.0, tungsten));
+    objects.add(left.clone());
+    objects.add(right.clone());
+
+    (
+        objects,
+        vec![
+            Arc::new(AreaLight::new(left).with_power(600.0).unwrap()),
+            Arc::new(AreaLight::new(right).with_power(600.0).unwrap()),
+        ],
+    )
+}
+
+pub fn cornell_box() -> (HittableList, Vec<Arc<dyn Light>>) {
     let mut objects = HittableList::default();
 
     let red = Arc::new(Lambertian::new(vector![0.65, 0.05, 0.05]));
@@ -158,9 +916,8 @@ pub fn cornell_box() -> HittableList {
 
     objects.add(Arc::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
     objects.add(Arc::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Arc::new(XZRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    )));
+    let light = Arc::new(XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light));
+    objects.add(light.clone());
     objects.add(Arc::new(XZRect::new(
         0.0,
         555.0,
@@ -204,10 +961,10 @@ pub fn cornell_box() -> HittableList {
     let box2 = Arc::new(Translate::new(box2, vector![130.0, 0.0, 65.0]));
     objects.add(box2);
 
-    objects
+    (objects, vec![Arc::new(AreaLight::new(light))])
 }
 
-pub fn cornell_smoke() -> HittableList {
+pub fn cornell_smoke() -> (HittableList, Vec<Arc<dyn Light>>) {
     let mut objects = HittableList::default();
 
     let red = Arc::new(Lambertian::new(vector![0.65, 0.05, 0.05]));
@@ -217,9 +974,8 @@ pub fn cornell_smoke() -> HittableList {
 
     objects.add(Arc::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
     objects.add(Arc::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Arc::new(XZRect::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
-    )));
+    let light = Arc::new(XZRect::new(113.0, 443.0, 127.0, 432.0, 554.0, light));
+    objects.add(light.clone());
     objects.add(Arc::new(XZRect::new(
         0.0,
         555.0,
@@ -272,10 +1028,10 @@ pub fn cornell_smoke() -> HittableList {
         vector![1.0, 1.0, 1.0],
     )));
 
-    objects
+    (objects, vec![Arc::new(AreaLight::new(light))])
 }
 
-pub fn final_scene() -> HittableList {
+pub fn final_scene() -> Result<(HittableList, Vec<Arc<dyn Light>>), AssetError> {
     let mut boxes1 = HittableList::default();
     let ground = Arc::new(Lambertian::new(vector![0.48, 0.83, 0.54]));
 
@@ -303,9 +1059,8 @@ pub fn final_scene() -> HittableList {
     objects.add(Arc::new(BvhNode::new(&boxes1, 0.0, 1.0)));
 
     let light = Arc::new(DiffuseLight::new(vector![7.0, 7.0, 7.0]));
-    objects.add(Arc::new(XZRect::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
-    )));
+    let light = Arc::new(XZRect::new(123.0, 423.0, 147.0, 412.0, 554.0, light));
+    objects.add(light.clone());
 
     let center1 = vector![400.0, 400.0, 200.0];
     let center2 = center1 + vector![30.0, 0.0, 0.0];
@@ -354,7 +1109,7 @@ pub fn final_scene() -> HittableList {
 
     let emat = Arc::new(Lambertian::new_from_texture(Arc::new(ImageTexture::new(
         PathBuf::from("earthmap.jpg"),
-    ))));
+    )?)));
     objects.add(Arc::new(Sphere::new(
         vector![400.0, 200.0, 400.0],
         100.0,
@@ -386,5 +1141,5 @@ pub fn final_scene() -> HittableList {
         vector![-100.0, 270.0, 395.0],
     )));
 
-    objects
+    Ok((objects, vec![Arc::new(AreaLight::new(light))]))
 }
@@ -0,0 +1,270 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+
+use crate::aabb::Aabb;
+use crate::background::Background;
+use crate::environment::EnvironmentMap;
+use crate::light::{ray_from_infinity, EmissionSample, Light, LightSample};
+use crate::random::random_in_cone;
+use crate::ray::Ray;
+use crate::spectrum::xyz_to_rgb;
+
+// Luminance in cd/m^2 that maps to a radiance of one.
+const LUMINANCE_SCALE: f64 = 20_000.0;
+// Luminance of the sun before the atmosphere attenuates it, in cd/m^2.
+const SUN_LUMINANCE: f64 = 2.0e9;
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+// Wavelengths in micrometres standing in for the red, green and blue channels.
+const WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+/// The coefficients of the Perez sky luminance distribution.
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Vector3<f64> {
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    xyz_to_rgb(&vector![big_x, luminance, big_z]).map(|c| c.max(0.0))
+}
+
+/// The Preetham et al. analytic model of a clear sky, without the sun itself. The sky is black
+/// below the horizon.
+pub struct PreethamSky {
+    sun_direction: Vector3<f64>,
+    perez_luminance: Perez,
+    perez_x: Perez,
+    perez_y: Perez,
+    zenith_luminance: f64,
+    zenith_x: f64,
+    zenith_y: f64,
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vector3<f64>, turbidity: f64) -> Self {
+        let t = turbidity;
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let theta_s2 = theta_s.powi(2);
+        let theta_s3 = theta_s.powi(3);
+        let zenith_x = t.powi(2) * (0.00166 * theta_s3 - 0.00375 * theta_s2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta_s3 + 0.06377 * theta_s2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_s3 - 0.21196 * theta_s2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t.powi(2) * (0.00275 * theta_s3 - 0.00610 * theta_s2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta_s3 + 0.08970 * theta_s2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_s3 - 0.26756 * theta_s2 + 0.06670 * theta_s + 0.26688);
+
+        Self {
+            sun_direction: sun_direction.normalize(),
+            perez_luminance: Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            perez_x: Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            perez_y: Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+            // The zenith luminance is in kcd/m^2.
+            zenith_luminance: zenith_luminance.max(0.0) * 1000.0,
+            zenith_x,
+            zenith_y,
+        }
+    }
+}
+
+impl Background for PreethamSky {
+    fn value(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        let direction = direction.normalize();
+        if direction.y <= 0.0 {
+            return vector![0.0, 0.0, 0.0];
+        }
+
+        // Keep away from the horizon, where the Perez function's 1/cos(theta) term blows up.
+        let cos_theta = direction.y.max(0.01);
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let cos_theta_s = self.sun_direction.y.max(0.01);
+        let theta_s = cos_theta_s.acos();
+
+        let relative = |perez: &Perez| perez.value(cos_theta, gamma) / perez.value(1.0, theta_s);
+        let luminance = self.zenith_luminance * relative(&self.perez_luminance);
+        let x = self.zenith_x * relative(&self.perez_x);
+        let y = self.zenith_y * relative(&self.perez_y);
+
+        xyy_to_rgb(x, y, luminance) / LUMINANCE_SCALE
+    }
+}
+
+/// The disk of the sun, dimmed and reddened by Rayleigh and aerosol scattering along its path
+/// through the atmosphere.
+pub struct Sun {
+    direction: Vector3<f64>,
+    radiance: Vector3<f64>,
+    cos_theta_max: f64,
+    world_center: Vector3<f64>,
+    world_radius: f64,
+}
+
+impl Sun {
+    pub fn new(direction: Vector3<f64>, turbidity: f64, world_bounds: Aabb) -> Self {
+        let direction = direction.normalize();
+        let (world_center, world_radius) = world_bounds.bounding_sphere();
+
+        let radiance = if direction.y <= 0.0 {
+            vector![0.0, 0.0, 0.0]
+        } else {
+            let theta_s = direction.y.acos();
+            let relative_air_mass =
+                1.0 / (direction.y + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+            let beta = 0.04608 * turbidity - 0.04586;
+            let transmittance = WAVELENGTHS.map(|lambda: f64| {
+                let rayleigh = -0.008735 * lambda.powf(-4.08) * relative_air_mass;
+                let aerosol = -beta * lambda.powf(-1.3) * relative_air_mass;
+                (rayleigh + aerosol).exp()
+            });
+            Vector3::from(transmittance) * SUN_LUMINANCE / LUMINANCE_SCALE
+        };
+
+        Self {
+            direction,
+            radiance,
+            cos_theta_max: SUN_ANGULAR_RADIUS.cos(),
+            world_center,
+            world_radius,
+        }
+    }
+
+    fn cone_pdf(&self) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - self.cos_theta_max))
+    }
+}
+
+impl Background for Sun {
+    fn value(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        if direction.normalize().dot(&self.direction) >= self.cos_theta_max {
+            self.radiance
+        } else {
+            vector![0.0, 0.0, 0.0]
+        }
+    }
+}
+
+impl Light for Sun {
+    fn sample_li(&self, p: &Vector3<f64>, _time: f64) -> Option<LightSample> {
+        if self.radiance == vector![0.0, 0.0, 0.0] {
+            return None;
+        }
+        let direction = random_in_cone(&self.direction, self.cos_theta_max);
+        Some(LightSample {
+            point: p + 2.0 * self.world_radius * direction,
+            normal: -direction,
+            radiance: self.radiance,
+            pdf: self.cone_pdf(),
+        })
+    }
+
+    fn pdf_li(&self, _p: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if wi.normalize().dot(&self.direction) >= self.cos_theta_max {
+            self.cone_pdf()
+        } else {
+            0.0
+        }
+    }
+
+    fn sample_le(&self, time: f64) -> Option<EmissionSample> {
+        if self.radiance == vector![0.0, 0.0, 0.0] {
+            return None;
+        }
+        let direction = random_in_cone(&self.direction, self.cos_theta_max);
+        Some(EmissionSample {
+            ray: ray_from_infinity(&direction, &self.world_center, self.world_radius, time),
+            normal: -direction,
+            radiance: self.radiance,
+            pdf_pos: 1.0 / (PI * self.world_radius.powi(2)),
+            pdf_dir: self.cone_pdf(),
+        })
+    }
+
+    fn pdf_le(&self, r: &Ray, _normal: &Vector3<f64>) -> (f64, f64) {
+        (
+            1.0 / (PI * self.world_radius.powi(2)),
+            self.pdf_li(&r.origin, &-r.direction),
+        )
+    }
+
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> bool {
+        false
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+/// A physically based daytime sky and sun, lit by sampling each separately.
+pub struct PhysicalSky {
+    sky: Arc<EnvironmentMap>,
+    sun: Arc<Sun>,
+}
+
+impl PhysicalSky {
+    /// Places the sun `elevation` degrees above the horizon and `azimuth` degrees around the
+    /// vertical axis from `+x` towards `+z`. `turbidity` ranges from about 2 for a very clear sky
+    /// to 10 for a hazy one.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, world_bounds: Aabb) -> Self {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_direction = vector![
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin()
+        ];
+
+        Self {
+            sky: Arc::new(EnvironmentMap::new(
+                Arc::new(PreethamSky::new(sun_direction, turbidity)),
+                256,
+                128,
+                world_bounds,
+            )),
+            sun: Arc::new(Sun::new(sun_direction, turbidity, world_bounds)),
+        }
+    }
+
+    pub fn lights(&self) -> Vec<Arc<dyn Light>> {
+        vec![self.sky.clone(), self.sun.clone()]
+    }
+}
+
+impl Background for PhysicalSky {
+    fn value(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        self.sky.value(direction) + self.sun.value(direction)
+    }
+}
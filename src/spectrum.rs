@@ -0,0 +1,349 @@
+use std::sync::OnceLock;
+
+use nalgebra::{vector, Matrix3, Vector3, Vector4};
+
+use crate::random::random_double;
+use crate::ray::Ray;
+
+// The range of visible wavelengths in nanometres.
+const LAMBDA_MIN: f64 = 360.0;
+const LAMBDA_MAX: f64 = 830.0;
+// The sodium D line, at which indices of refraction are conventionally quoted.
+const LAMBDA_D: f64 = 587.6;
+// The number of wavelengths traced together along each path in spectral mode.
+const WAVELENGTH_SAMPLES: usize = 4;
+
+// The relative spectral power of CIE standard illuminant D65, every 10 nm from 360 nm to 830 nm.
+const D65: [f64; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008,
+    117.812, 114.861, 115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0,
+    96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146,
+    82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054,
+    63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+/// How a material's index of refraction varies with wavelength.
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    /// Cauchy's equation `n = a + b / λ²`, with `λ` in micrometres.
+    Cauchy { a: f64, b: f64 },
+    /// The Sellmeier equation `n² = 1 + Σ bᵢλ² / (λ² - cᵢ)`, with `λ` in micrometres.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott SF11, a dense flint glass with strong dispersion.
+    pub const DENSE_FLINT: Self = Self::Sellmeier {
+        b: [1.737_596_95, 0.313_747_346, 1.898_781_01],
+        c: [0.013_188_707, 0.062_306_814_2, 155.236_29],
+    };
+
+    /// The index of refraction at `wavelength` nanometres.
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let lambda2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Self::Cauchy { a, b } => a + b / lambda2,
+            Self::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c)
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+
+    /// The index of refraction quoted for the material, used by rays without a wavelength.
+    pub fn nominal_ior(&self) -> f64 {
+        self.ior(LAMBDA_D)
+    }
+}
+
+/// A measured spectral power distribution, such as a standard illuminant, scaled to a luminance of
+/// one.
+pub struct Spectrum {
+    wavelengths: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl Spectrum {
+    /// Interpolates linearly between `values` at the increasing `wavelengths` in nanometres.
+    pub fn new(wavelengths: Vec<f64>, values: Vec<f64>) -> Self {
+        assert_eq!(wavelengths.len(), values.len());
+        let mut spectrum = Self {
+            wavelengths,
+            values,
+        };
+        let luminance = integrate(|lambda| spectrum.value(lambda) * colour_matching(lambda).y);
+        for value in &mut spectrum.values {
+            *value /= luminance;
+        }
+        spectrum
+    }
+
+    /// CIE standard illuminant D65, representing average daylight and the white point of sRGB.
+    pub fn d65() -> Self {
+        let wavelengths = (0..D65.len()).map(|i| 360.0 + 10.0 * (i as f64)).collect();
+        Self::new(wavelengths, D65.to_vec())
+    }
+
+    /// The emission of a black body at `temperature` kelvin. CIE standard illuminant A, a
+    /// tungsten filament lamp, is a black body at 2856 K.
+    pub fn blackbody(temperature: f64) -> Self {
+        const C: f64 = 2.997_924_58e8;
+        const H: f64 = 6.626_070_15e-34;
+        const K: f64 = 1.380_649e-23;
+
+        let wavelengths: Vec<f64> = (0..=94).map(|i| LAMBDA_MIN + 5.0 * (i as f64)).collect();
+        let values = wavelengths
+            .iter()
+            .map(|lambda| {
+                let lambda = lambda * 1e-9;
+                2.0 * H * C * C
+                    / (lambda.powi(5) * ((H * C / (lambda * K * temperature)).exp() - 1.0))
+            })
+            .collect();
+        Self::new(wavelengths, values)
+    }
+
+    /// The relative power at `wavelength` nanometres, which is zero outside of the measurements.
+    pub fn value(&self, wavelength: f64) -> f64 {
+        let last = self.wavelengths.len() - 1;
+        if wavelength < self.wavelengths[0] || wavelength > self.wavelengths[last] {
+            return 0.0;
+        }
+        let i = self
+            .wavelengths
+            .partition_point(|&lambda| lambda <= wavelength)
+            .clamp(1, last);
+        let (lambda0, lambda1) = (self.wavelengths[i - 1], self.wavelengths[i]);
+        let t = (wavelength - lambda0) / (lambda1 - lambda0);
+        (1.0 - t) * self.values[i - 1] + t * self.values[i]
+    }
+
+    /// The colour of the light in linear sRGB, which has a luminance of one.
+    pub fn colour(&self) -> Vector3<f64> {
+        xyz_to_rgb(&integrate(|lambda| {
+            self.value(lambda) * colour_matching(lambda)
+        }))
+    }
+}
+
+/// Sums `f` over the visible range in steps of one nanometre.
+fn integrate<T: std::iter::Sum>(f: impl Fn(f64) -> T) -> T {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    (0..steps).map(|i| f(LAMBDA_MIN + (i as f64) + 0.5)).sum()
+}
+
+fn daylight() -> &'static Spectrum {
+    static DAYLIGHT: OnceLock<Spectrum> = OnceLock::new();
+    DAYLIGHT.get_or_init(Spectrum::d65)
+}
+
+/// The CIE 1931 colour matching functions, using the multi-lobe fit of Wyman et al.'s "Simple
+/// Analytic Approximations to the CIE XYZ Color Matching Functions".
+fn colour_matching(wavelength: f64) -> Vector3<f64> {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if wavelength < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        (-0.5 * ((wavelength - mu) / sigma).powi(2)).exp()
+    };
+    vector![
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8)
+    ]
+}
+
+/// Converts CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(xyz: &Vector3<f64>) -> Vector3<f64> {
+    vector![
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z
+    ]
+}
+
+/// The colour of a single wavelength, dropping the negative lobes that fall outside of sRGB.
+fn wavelength_rgb(wavelength: f64) -> Vector3<f64> {
+    xyz_to_rgb(&colour_matching(wavelength)).map(|c| c.max(0.0))
+}
+
+fn wavelength_pdf(wavelength: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&wavelength) {
+        return 0.0;
+    }
+    0.003_939_804_2 / (0.0072 * (wavelength - 538.0)).cosh().powi(2)
+}
+
+fn sample_visible_wavelength(u: f64) -> f64 {
+    538.0 - 138.888_889 * (0.856_910_62 - 1.827_501_97 * u).atanh()
+}
+
+/// Picks a wavelength in nanometres, concentrating samples where the eye is most sensitive.
+pub fn sample_wavelength() -> f64 {
+    sample_visible_wavelength(random_double())
+}
+
+/// The weight that turns a single wavelength sample from `sample_wavelength` back into RGB. Each
+/// channel averages to one over all wavelengths, so white light stays white.
+pub fn wavelength_weight(wavelength: f64) -> Vector3<f64> {
+    static INTEGRALS: OnceLock<Vector3<f64>> = OnceLock::new();
+    let integrals = INTEGRALS.get_or_init(|| integrate(wavelength_rgb));
+
+    let pdf = wavelength_pdf(wavelength);
+    if pdf == 0.0 {
+        return vector![0.0, 0.0, 0.0];
+    }
+    wavelength_rgb(wavelength).component_div(integrals) / pdf
+}
+
+/// Narrows `r` down to `wavelength` before it meets a dispersive material, returning the weight
+/// to apply to the path's throughput. Rays that already carry a wavelength keep it.
+pub fn narrow_to_wavelength(r: &mut Ray, wavelength: f64) -> Vector3<f64> {
+    if r.wavelength.is_some() {
+        return vector![1.0, 1.0, 1.0];
+    }
+    r.wavelength = Some(wavelength);
+    wavelength_weight(wavelength)
+}
+
+/// The weights of the red, green and blue basis spectra at `wavelength`. They sum to one, so that
+/// white uplifts to a constant spectrum.
+fn uplift_basis(wavelength: f64) -> Vector3<f64> {
+    let rgb = wavelength_rgb(wavelength);
+    let sum = rgb.sum();
+    if sum > 0.0 {
+        rgb / sum
+    } else {
+        vector![1.0, 1.0, 1.0] / 3.0
+    }
+}
+
+/// Maps an RGB reflectance to the mix of basis spectra that has that colour under D65.
+fn uplift_matrix() -> &'static Matrix3<f64> {
+    static MATRIX: OnceLock<Matrix3<f64>> = OnceLock::new();
+    MATRIX.get_or_init(|| {
+        let basis_colours = integrate(|lambda| {
+            xyz_to_rgb(&(daylight().value(lambda) * colour_matching(lambda)))
+                * uplift_basis(lambda).transpose()
+        });
+        basis_colours.try_inverse().unwrap()
+    })
+}
+
+/// A hero wavelength in nanometres and others evenly spaced through the visible range from it,
+/// which a path carries together in spectral mode.
+pub struct SampledWavelengths {
+    lambda: [f64; WAVELENGTH_SAMPLES],
+    pdf: [f64; WAVELENGTH_SAMPLES],
+    basis: [Vector3<f64>; WAVELENGTH_SAMPLES],
+    daylight: Vector4<f64>,
+}
+
+impl SampledWavelengths {
+    pub fn sample() -> Self {
+        let u = random_double();
+        let lambda = std::array::from_fn(|i| {
+            sample_visible_wavelength((u + (i as f64) / (WAVELENGTH_SAMPLES as f64)).fract())
+        });
+        Self {
+            lambda,
+            pdf: lambda.map(wavelength_pdf),
+            basis: lambda.map(uplift_basis),
+            daylight: Vector4::from(lambda.map(|lambda| daylight().value(lambda))),
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Drops all but the hero wavelength, once dispersion has sent the others in other
+    /// directions.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|&pdf| pdf == 0.0) {
+            return;
+        }
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= WAVELENGTH_SAMPLES as f64;
+    }
+
+    /// Narrows `r` down to the hero wavelength before it meets a dispersive material in an RGB
+    /// integrator, returning the weight to apply to the path's throughput. Rays that already
+    /// carry a wavelength keep it.
+    pub fn narrow(&mut self, r: &mut Ray) -> Vector3<f64> {
+        if r.wavelength.is_some() {
+            return vector![1.0, 1.0, 1.0];
+        }
+        self.terminate_secondary();
+        narrow_to_wavelength(r, self.hero())
+    }
+
+    /// A reflectance spectrum with the colour `rgb`, evaluated at each wavelength.
+    pub fn uplift_reflectance(&self, rgb: &Vector3<f64>) -> Vector4<f64> {
+        let weights = uplift_matrix() * rgb;
+        Vector4::from(self.basis.map(|basis| basis.dot(&weights).max(0.0)))
+    }
+
+    /// An emission spectrum with the colour `rgb`, evaluated at each wavelength. White light is
+    /// taken to be D65.
+    pub fn uplift_illuminant(&self, rgb: &Vector3<f64>) -> Vector4<f64> {
+        self.uplift_reflectance(rgb).component_mul(&self.daylight)
+    }
+
+    pub fn evaluate(&self, spectrum: &Spectrum) -> Vector4<f64> {
+        Vector4::from(self.lambda.map(|lambda| spectrum.value(lambda)))
+    }
+
+    /// Converts radiance at each wavelength into an estimate of its CIE XYZ colour.
+    pub fn to_xyz(&self, radiance: &Vector4<f64>) -> Vector3<f64> {
+        (0..WAVELENGTH_SAMPLES)
+            .filter(|&i| self.pdf[i] > 0.0)
+            .map(|i| radiance[i] * colour_matching(self.lambda[i]) / self.pdf[i])
+            .sum::<Vector3<f64>>()
+            / (WAVELENGTH_SAMPLES as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{vector, Vector3};
+
+    use super::{sample_visible_wavelength, wavelength_weight, Dispersion, Spectrum, LAMBDA_D};
+
+    #[test]
+    fn dense_flint_has_its_catalogue_index_at_the_d_line() {
+        // Schott's catalogue gives SF11 an index of 1.78472 at the sodium D line.
+        assert!((Dispersion::DENSE_FLINT.ior(LAMBDA_D) - 1.78472).abs() < 1e-4);
+        assert!(Dispersion::DENSE_FLINT.ior(450.0) > Dispersion::DENSE_FLINT.ior(650.0));
+
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.ior(400.0) - 1.525).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spectra_interpolate_between_measurements_and_vanish_outside_them() {
+        let spectrum = Spectrum::new(vec![400.0, 500.0, 600.0], vec![1.0, 3.0, 2.0]);
+        let at = |lambda| spectrum.value(lambda) / spectrum.value(400.0);
+        assert!((at(450.0) - 2.0).abs() < 1e-12);
+        assert!((at(500.0) - 3.0).abs() < 1e-12);
+        assert!((at(575.0) - 2.25).abs() < 1e-12);
+        assert_eq!(spectrum.value(399.0), 0.0);
+        assert_eq!(spectrum.value(601.0), 0.0);
+    }
+
+    #[test]
+    fn white_light_stays_white_over_sampled_wavelengths() {
+        const N: usize = 100_000;
+        let mean = (0..N)
+            .map(|i| wavelength_weight(sample_visible_wavelength((i as f64 + 0.5) / N as f64)))
+            .sum::<Vector3<f64>>()
+            / N as f64;
+        assert!((mean - vector![1.0, 1.0, 1.0]).amax() < 1e-2, "{mean}");
+    }
+}
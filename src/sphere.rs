@@ -1,6 +1,7 @@
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
+use crate::random::random_unit_vector;
 use crate::ray::Ray;
 use nalgebra::{vector, Vector3};
 use std::f64::consts::PI;
@@ -28,6 +29,14 @@ impl Sphere {
 
         (phi / (2.0 * PI), theta / PI)
     }
+
+    /// The rates of change with u and v of a point `p` on the unit sphere.
+    fn get_sphere_tangents(p: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+        let sin_theta = (1.0 - p.y * p.y).sqrt().max(1e-8);
+        let dpdu = 2.0 * PI * vector![p.z, 0.0, -p.x];
+        let dpdv = PI * vector![-p.x * p.y / sin_theta, sin_theta, -p.y * p.z / sin_theta];
+        (dpdu, dpdv)
+    }
 }
 
 impl Hittable for Sphere {
@@ -56,7 +65,12 @@ impl Hittable for Sphere {
         let p = r.at(t);
         let normal = (p - self.center) / self.radius;
         let (u, v) = Self::get_sphere_uv(&normal);
-        Some(HitRecord::new(p, normal, self.material.clone(), t, u, v, r))
+        let (dpdu, dpdv) = Self::get_sphere_tangents(&normal);
+        Some(
+            HitRecord::new(p, normal, self.material.clone(), t, u, v, r)
+                .with_curvature(dpdu, dpdv)
+                .with_tangents(dpdu * self.radius, dpdv * self.radius),
+        )
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
@@ -65,4 +79,20 @@ impl Hittable for Sphere {
             self.center + vector![self.radius, self.radius, self.radius],
         ))
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius.powi(2)
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        let normal = random_unit_vector();
+        let (u, v) = Self::get_sphere_uv(&normal);
+        Some(HitRecord::on_surface(
+            self.center + self.radius * normal,
+            normal,
+            self.material.clone(),
+            u,
+            v,
+        ))
+    }
 }
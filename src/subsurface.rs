@@ -0,0 +1,232 @@
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector3};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Isotropic, Material};
+use crate::random::{random_double, random_int, random_unit_vector};
+use crate::ray::Ray;
+
+/// A closed object whose interior scatters light, like skin, wax, marble or milk. Light enters and
+/// leaves through the boundary's own material, typically a `Dielectric`, and takes a random walk
+/// through a homogeneous medium in between.
+pub struct Subsurface {
+    boundary: Arc<dyn Hittable>,
+    single_scattering_albedo: Vector3<f64>,
+    extinction: Vector3<f64>,
+}
+
+impl Subsurface {
+    /// `albedo` is the colour of the object as a whole, after many scattering events, and
+    /// `mean_free_path` the average distance light of each colour travels between them.
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        albedo: Vector3<f64>,
+        mean_free_path: Vector3<f64>,
+    ) -> Self {
+        Self {
+            boundary,
+            single_scattering_albedo: albedo.map(single_scattering_albedo),
+            extinction: mean_free_path.map(|distance| 1.0 / distance),
+        }
+    }
+}
+
+// Beyond this many scattering events a walk is counted as absorbed.
+const MAX_EVENTS: usize = 1024;
+
+/// The albedo of a single scattering event that gives `albedo` after many, from Chiang et al.,
+/// "Practical and Controllable Subsurface Scattering for Production Path Tracing".
+fn single_scattering_albedo(albedo: f64) -> f64 {
+    let albedo = albedo.clamp(0.0, 1.0);
+    let s =
+        4.09712 + 4.20863 * albedo - (9.59217 + 41.6808 * albedo + 17.7126 * albedo.powi(2)).sqrt();
+    1.0 - s * s
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let boundary = self.boundary.hit(r, t_min, f64::INFINITY)?;
+        if boundary.t() >= t_max {
+            return None;
+        }
+        if boundary.front_face() {
+            return Some(boundary);
+        }
+
+        // A ray that next crosses the boundary from behind is inside, and walks through the
+        // medium until it reaches the boundary again. Distances are sampled for one channel picked
+        // at random, and each channel weighted by its own contribution over the density of the
+        // whole walk averaged over all three, which keeps the weights bounded however long it is.
+        // Both are kept relative to the sampled channel's density to stay in range.
+        let entered_at = boundary.t();
+        let channel = random_int(0, 2) as usize;
+        let mut throughput = vector![1.0, 1.0, 1.0];
+        let mut densities = vector![1.0, 1.0, 1.0];
+        let mut ray = *r;
+        let mut exit = boundary;
+        for _ in 0..MAX_EVENTS {
+            let speed = ray.direction.norm();
+            let distance = -random_double().ln() / self.extinction[channel];
+            if distance / speed >= exit.t() {
+                let transmittance = (-self.extinction * exit.t() * speed).map(f64::exp);
+                throughput.component_mul_assign(&(transmittance / transmittance[channel]));
+                densities.component_mul_assign(&(transmittance / transmittance[channel]));
+                let material = Arc::new(Transmitted {
+                    material: exit.material().clone(),
+                    weight: throughput / densities.mean(),
+                    ray,
+                });
+                return Some(exit.with_material(material).with_t(entered_at));
+            }
+
+            let transmittance = (-self.extinction * distance).map(f64::exp);
+            let density = self.extinction.component_mul(&transmittance);
+            let scattering = density.component_mul(&self.single_scattering_albedo);
+            throughput.component_mul_assign(&(scattering / density[channel]));
+            densities.component_mul_assign(&(density / density[channel]));
+
+            // Walks that have lost most of their light end early, or carry on for all of those
+            // that don't.
+            let survival = (throughput / densities.mean()).max().min(1.0);
+            if random_double() >= survival {
+                break;
+            }
+            throughput /= survival;
+
+            let mut scattered = Ray::new(ray.at(distance / speed), random_unit_vector(), ray.time);
+            scattered.wavelength = r.wavelength;
+            ray = scattered;
+            exit = match self.boundary.hit(&ray, t_min, f64::INFINITY) {
+                Some(rec) if !rec.front_face() => rec,
+                _ => break,
+            };
+        }
+
+        // Light that doesn't make it out is absorbed where the walk entered.
+        Some(
+            exit.with_material(Arc::new(Isotropic::new(Vector3::zeros())))
+                .with_t(entered_at),
+        )
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+/// The boundary as seen by light leaving a walk through the medium, weighted by what each
+/// channel kept of it and met along the walk's last ray rather than the one that entered.
+struct Transmitted {
+    material: Arc<dyn Material>,
+    weight: Vector3<f64>,
+    ray: Ray,
+}
+
+impl Material for Transmitted {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Vector3<f64>, Ray)> {
+        let (attenuation, scattered) = self.material.scatter(&self.ray, rec)?;
+        Some((attenuation.component_mul(&self.weight), scattered))
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Vector3<f64> {
+        self.material
+            .eval(&self.ray, rec, scattered)
+            .component_mul(&self.weight)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(&self.ray, rec, scattered)
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.material.is_specular(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn shading_normal(&self, rec: &HitRecord) -> Option<Vector3<f64>> {
+        Some(
+            rec.clone()
+                .with_material(self.material.clone())
+                .shading_normal(),
+        )
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Vector3<f64> {
+        self.material
+            .emitted(&self.ray, rec)
+            .component_mul(&self.weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::{vector, Vector3};
+
+    use super::{single_scattering_albedo, Subsurface};
+    use crate::hittable::Hittable;
+    use crate::material::DiffuseLight;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+
+    /// The average weight on light leaving `walks` walks that start at the centre of a unit ball,
+    /// read from a boundary that emits white.
+    fn mean_exit_weight(albedo: Vector3<f64>, mean_free_path: Vector3<f64>) -> Vector3<f64> {
+        const WALKS: usize = 5_000;
+        let glowing = Arc::new(DiffuseLight::new(vector![1.0, 1.0, 1.0]));
+        let ball = Arc::new(Sphere::new(vector![0.0, 0.0, 0.0], 1.0, glowing));
+        let medium = Subsurface::new(ball, albedo, mean_free_path);
+
+        let r = Ray::new(vector![0.0, 0.0, 0.0], vector![0.0, 0.0, 1.0], 0.0);
+        (0..WALKS)
+            .map(|_| {
+                let rec = medium.hit(&r, 0.001, f64::INFINITY).unwrap();
+                rec.material().emitted(&r, &rec)
+            })
+            .sum::<Vector3<f64>>()
+            / WALKS as f64
+    }
+
+    #[test]
+    fn single_scattering_albedo_spans_black_to_white() {
+        assert!(single_scattering_albedo(0.0).abs() < 1e-4);
+        assert!((single_scattering_albedo(1.0) - 1.0).abs() < 1e-4);
+        // Many events darken the colour, so each has to scatter more than the whole does.
+        for albedo in [0.1, 0.5, 0.9] {
+            assert!(single_scattering_albedo(albedo) > albedo);
+        }
+    }
+
+    #[test]
+    fn walks_without_absorption_let_all_the_light_out() {
+        let mean = mean_exit_weight(vector![1.0, 1.0, 1.0], vector![0.2, 0.3, 0.5]);
+        assert!((mean - vector![1.0, 1.0, 1.0]).amax() < 0.06, "{mean}");
+    }
+
+    #[test]
+    fn walks_keep_more_of_the_colours_with_higher_albedo() {
+        let mean = mean_exit_weight(vector![0.9, 0.5, 0.1], vector![0.3, 0.3, 0.3]);
+        assert!(
+            mean.x > mean.y && mean.y > mean.z && mean.z >= 0.0,
+            "{mean}"
+        );
+    }
+
+    #[test]
+    fn rays_from_outside_stop_at_the_boundary() {
+        let glowing = Arc::new(DiffuseLight::new(vector![1.0, 1.0, 1.0]));
+        let ball = Arc::new(Sphere::new(vector![0.0, 0.0, 0.0], 1.0, glowing));
+        let medium = Subsurface::new(ball, vector![0.5, 0.5, 0.5], vector![0.1, 0.1, 0.1]);
+
+        let r = Ray::new(vector![0.0, 0.0, -3.0], vector![0.0, 0.0, 1.0], 0.0);
+        let rec = medium.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.front_face() && (rec.t() - 2.0).abs() < 1e-9);
+        assert!(medium.hit(&r, 0.001, 1.5).is_none());
+    }
+}
@@ -1,13 +1,61 @@
-use std::{path::PathBuf, sync::Arc};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use image::io::Reader as ImageReader;
-use image::RgbImage;
-use nalgebra::{vector, Vector3};
+use nalgebra::{vector, Vector2, Vector3, Vector4};
 
+use crate::asset::AssetError;
+use crate::hittable::HitRecord;
 use crate::perlin::Perlin;
+use crate::texture_cache::{CachedImage, TextureCache};
+
+pub fn luminance(colour: &Vector3<f64>) -> f64 {
+    0.2126 * colour.x + 0.7152 * colour.y + 0.0722 * colour.z
+}
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64>;
+
+    /// The value at `rec`, which textures that can filter over the footprint of the ray that
+    /// found it override.
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.value(rec.u(), rec.v(), &rec.point())
+    }
+
+    /// The opacity at (u, v, p), from 0 for a hole to 1 for solid. Textures without an alpha
+    /// channel use their luminance, so that any of them can serve as a mask.
+    fn alpha(&self, u: f64, v: f64, p: &Vector3<f64>) -> f64 {
+        luminance(&self.value(u, v, p)).clamp(0.0, 1.0)
+    }
+}
+
+/// Anything that can stand in for a texture, so that a material parameter can be given either a
+/// constant or a texture.
+pub trait IntoTexture {
+    fn into_texture(self) -> Arc<dyn Texture>;
+}
+
+impl IntoTexture for f64 {
+    fn into_texture(self) -> Arc<dyn Texture> {
+        Arc::new(SolidColour::new(vector![self, self, self]))
+    }
+}
+
+impl IntoTexture for Vector3<f64> {
+    fn into_texture(self) -> Arc<dyn Texture> {
+        Arc::new(SolidColour::new(self))
+    }
+}
+
+impl<T: Texture + 'static> IntoTexture for Arc<T> {
+    fn into_texture(self) -> Arc<dyn Texture> {
+        self
+    }
+}
+
+impl IntoTexture for Arc<dyn Texture> {
+    fn into_texture(self) -> Arc<dyn Texture> {
+        self
+    }
 }
 
 pub struct SolidColour {
@@ -26,29 +74,119 @@ impl Texture for SolidColour {
     }
 }
 
+/// How a `CheckerTexture` lays out its squares.
+#[derive(Clone, Copy)]
+pub enum CheckerPattern {
+    /// The sign of a product of sines in world space, about 3 squares per unit length. Squares
+    /// stretch and pinch wherever a surface runs at an angle to the axes.
+    Sines,
+    /// Cubes of side `cell_size` filling space, so that any surface cuts through squares.
+    Solid { cell_size: f64 },
+    /// Squares in uv space, repeating `u_repeats` times across u and `v_repeats` times across v.
+    Uv { u_repeats: f64, v_repeats: f64 },
+}
+
+/// Alternates between two textures in a checkerboard. Solid and uv patterns are antialiased by
+/// blending the two by how much of each the ray's footprint covers.
 pub struct CheckerTexture {
     odd: Arc<dyn Texture>,
     even: Arc<dyn Texture>,
+    pattern: CheckerPattern,
 }
 
 impl CheckerTexture {
     pub fn new(c1: Vector3<f64>, c2: Vector3<f64>) -> Self {
+        Self::new_from_textures(
+            Arc::new(SolidColour::new(c1)),
+            Arc::new(SolidColour::new(c2)),
+        )
+    }
+
+    pub fn new_from_textures(even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
         Self {
-            even: Arc::new(SolidColour::new(c1)),
-            odd: Arc::new(SolidColour::new(c2)),
+            even,
+            odd,
+            pattern: CheckerPattern::Sines,
         }
     }
+
+    pub fn with_pattern(mut self, pattern: CheckerPattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// How much of a box of the given half-widths about (u, v, p) lies in odd squares, with the
+    /// widths in units of squares.
+    fn odd_weight(&self, u: f64, v: f64, p: &Vector3<f64>, widths: &Vector3<f64>) -> f64 {
+        let cells = match self.pattern {
+            CheckerPattern::Sines => {
+                let sines = (10.0 * p.x).sin() * (10.0 * p.y).sin() * (10.0 * p.z).sin();
+                return if sines < 0.0 { 1.0 } else { 0.0 };
+            }
+            CheckerPattern::Solid { cell_size } => p / cell_size,
+            CheckerPattern::Uv {
+                u_repeats,
+                v_repeats,
+            } => vector![u * u_repeats, v * v_repeats, 0.0],
+        };
+        // A square is odd when an odd number of its coordinates are, so with independent odds
+        // of each, the chance of that is (1 - product of (1 - 2 * odds)) / 2.
+        let evenness = cells
+            .zip_map(widths, |x, width| 1.0 - 2.0 * odd_fraction(x, width))
+            .product();
+        0.5 * (1.0 - evenness)
+    }
+
+    /// The half-widths in squares of the footprint of the ray that found `rec`, or zero without
+    /// differentials.
+    fn footprint(&self, rec: &HitRecord) -> Vector3<f64> {
+        let footprint = match self.pattern {
+            CheckerPattern::Sines => None,
+            CheckerPattern::Solid { cell_size } => rec
+                .dpdxy()
+                .map(|(dpdx, dpdy)| 0.5 * dpdx.abs().sup(&dpdy.abs()) / cell_size),
+            CheckerPattern::Uv {
+                u_repeats,
+                v_repeats,
+            } => rec.uv_footprint().map(|(duvdx, duvdy)| {
+                let width = 0.5 * duvdx.abs().sup(&duvdy.abs());
+                vector![width.x * u_repeats, width.y * v_repeats, 0.0]
+            }),
+        };
+        footprint.unwrap_or_else(Vector3::zeros)
+    }
+}
+
+/// The fraction of `[x - width, x + width]` covered by odd unit squares.
+fn odd_fraction(x: f64, width: f64) -> f64 {
+    if width < 1e-9 {
+        return x.floor().rem_euclid(2.0);
+    }
+    // The integral of the square wave that is 1 over odd squares and 0 over even ones.
+    let integral = |x: f64| {
+        let periods = (0.5 * x).floor();
+        periods + (x - 2.0 * periods - 1.0).max(0.0)
+    };
+    (integral(x + width) - integral(x - width)) / (2.0 * width)
 }
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
-        let sines = (10.0 * p.x).sin() * (10.0 * p.y).sin() * (10.0 * p.z).sin();
-        if sines < 0.0 {
+        if self.odd_weight(u, v, p, &Vector3::zeros()) > 0.5 {
             self.odd.value(u, v, p)
         } else {
             self.even.value(u, v, p)
         }
     }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        let weight = self.odd_weight(rec.u(), rec.v(), &rec.point(), &self.footprint(rec));
+        match weight {
+            w if w <= 0.0 => self.even.value_at(rec),
+            w if w >= 1.0 => self.odd.value_at(rec),
+            w => self.even.value_at(rec) * (1.0 - w) + self.odd.value_at(rec) * w,
+        }
+    }
 }
 
 pub struct NoiseTexture {
@@ -71,34 +209,220 @@ impl Texture for NoiseTexture {
     }
 }
 
+/// How an `ImageTexture` fills uv coordinates outside its image.
+#[derive(Clone, Copy)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+/// How an `ImageTexture` blends the pixels around a lookup.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom over the nearest four by four pixels.
+    Bicubic,
+}
+
+/// An image mapped onto the uv square. Lookups first rotate uv about the centre of the square,
+/// then scale and offset it, so a scale of 2 repeats the image twice across the surface.
+#[derive(Clone)]
 pub struct ImageTexture {
-    image: RgbImage,
+    image: Arc<CachedImage>,
+    wrap: WrapMode,
+    filter: Filter,
+    scale: Vector2<f64>,
+    offset: Vector2<f64>,
+    rotation: f64,
 }
 
 impl ImageTexture {
-    pub fn new(image_path: PathBuf) -> Self {
-        Self {
-            image: ImageReader::open(image_path)
-                .unwrap()
-                .decode()
-                .unwrap()
-                .into_rgb8(),
+    /// Textures of the same image share its pixels through the `TextureCache`.
+    pub fn new(image_path: PathBuf) -> Result<Self, AssetError> {
+        Ok(Self {
+            image: TextureCache::global().image(image_path, true)?,
+            wrap: WrapMode::Repeat,
+            filter: Filter::Bilinear,
+            scale: vector![1.0, 1.0],
+            offset: vector![0.0, 0.0],
+            rotation: 0.0,
+        })
+    }
+
+    /// Whether the image holds sRGB-encoded colour, as it does by default, rather than linear
+    /// data such as a normal or roughness map.
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.image = TextureCache::global().in_colour_space(&self.image, srgb);
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_scale(mut self, u: f64, v: f64) -> Self {
+        self.scale = vector![u, v];
+        self
+    }
+
+    pub fn with_offset(mut self, u: f64, v: f64) -> Self {
+        self.offset = vector![u, v];
+        self
+    }
+
+    /// Rotates the image anticlockwise by `degrees` about the centre of the uv square.
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    /// The pixel at column `i` and row `j` of mip level `level`, wrapped onto the image, in
+    /// linear RGB with alpha.
+    fn texel(&self, level: usize, i: i64, j: i64) -> Vector4<f64> {
+        let (width, height) = self.image.size(level);
+        self.image.texel(
+            level,
+            self.wrap.apply(i, width) as u32,
+            self.wrap.apply(j, height) as u32,
+        )
+    }
+
+    /// Rotates, scales and offsets a change in uv, without the offset.
+    fn transform_direction(&self, duv: &Vector2<f64>) -> Vector2<f64> {
+        let (sin, cos) = self.rotation.sin_cos();
+        vector![cos * duv.x - sin * duv.y, sin * duv.x + cos * duv.y].component_mul(&self.scale)
+    }
+
+    fn transform(&self, u: f64, v: f64) -> Vector2<f64> {
+        let centre = vector![0.5, 0.5];
+        self.transform_direction(&(vector![u, v] - centre))
+            + centre.component_mul(&self.scale)
+            + self.offset
+    }
+
+    /// The position of `uv` in pixels of mip level `level`, with pixel centres at whole numbers
+    /// and rows running down the image.
+    fn pixel_position(&self, level: usize, uv: &Vector2<f64>) -> (f64, f64) {
+        let (width, height) = self.image.size(level);
+        (
+            uv.x * width as f64 - 0.5,
+            (1.0 - uv.y) * height as f64 - 0.5,
+        )
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Vector4<f64> {
+        let (x, y) = self.pixel_position(0, &self.transform(u, v));
+        match self.filter {
+            Filter::Nearest => self.texel(0, x.round() as i64, y.round() as i64),
+            Filter::Bilinear => bilinear(x, y, |i, j| self.texel(0, i, j)),
+            Filter::Bicubic => {
+                let (i, j) = (x.floor() as i64, y.floor() as i64);
+                let wx = catmull_rom_weights(x - x.floor());
+                let wy = catmull_rom_weights(y - y.floor());
+                let mut sum = Vector4::zeros();
+                for (dj, wy) in wy.iter().enumerate() {
+                    for (di, wx) in wx.iter().enumerate() {
+                        sum += self.texel(0, i + di as i64 - 1, j + dj as i64 - 1) * (wx * wy);
+                    }
+                }
+                // Catmull-Rom overshoots at sharp edges.
+                sum.map(|c| c.max(0.0))
+            }
         }
     }
+
+    /// Filters over the footprint of the ray that found `rec`, blending between the two mip
+    /// levels whose pixels best match it in size. Falls back to `sample` when the image is
+    /// magnified or the ray has no differentials.
+    fn filtered(&self, rec: &HitRecord) -> Vector4<f64> {
+        let Some((duvdx, duvdy)) = rec.uv_footprint() else {
+            return self.sample(rec.u(), rec.v());
+        };
+        let (width, height) = self.image.size(0);
+        let size = vector![width as f64, height as f64];
+        let width = (self.transform_direction(&duvdx).component_mul(&size).norm())
+            .max(self.transform_direction(&duvdy).component_mul(&size).norm());
+        let level = width.max(1e-12).log2();
+        if level <= 0.0 {
+            return self.sample(rec.u(), rec.v());
+        }
+
+        let levels = self.image.mip_levels();
+        let level = level.min((levels - 1) as f64);
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(levels - 1);
+        let fraction = level - lower as f64;
+
+        let uv = self.transform(rec.u(), rec.v());
+        let sample = |level: usize| {
+            let (x, y) = self.pixel_position(level, &uv);
+            bilinear(x, y, |i, j| self.texel(level, i, j))
+        };
+        sample(lower) * (1.0 - fraction) + sample(upper) * fraction
+    }
 }
 
-impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, _p: &Vector3<f64>) -> Vector3<f64> {
-        let u = u.clamp(0.0, 1.0);
-        let v = 1.0 - v.clamp(0.0, 1.0);
+impl WrapMode {
+    /// Wraps pixel index `i` onto an image `size` pixels across.
+    fn apply(self, i: i64, size: u32) -> i64 {
+        let size = size as i64;
+        match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+            WrapMode::Clamp => i.clamp(0, size - 1),
+        }
+    }
+}
 
-        let i = ((u * (self.image.width() as f64)) as u32).min(self.image.width() - 1);
-        let j = ((v * (self.image.height() as f64)) as u32).min(self.image.height() - 1);
+/// Blends the four pixels around `(x, y)`, given in pixels with centres at whole numbers.
+fn bilinear(x: f64, y: f64, texel: impl Fn(i64, i64) -> Vector4<f64>) -> Vector4<f64> {
+    let (i, j) = (x.floor() as i64, y.floor() as i64);
+    let (fx, fy) = (x - x.floor(), y - y.floor());
+    let top = texel(i, j) * (1.0 - fx) + texel(i + 1, j) * fx;
+    let bottom = texel(i, j + 1) * (1.0 - fx) + texel(i + 1, j + 1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
 
-        let colour_scale = 1.0 / 255.0;
+/// The weights of the four pixels around a point `t` of the way from the second to the third.
+fn catmull_rom_weights(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Vector3<f64>) -> Vector3<f64> {
+        self.sample(u, v).xyz()
+    }
 
-        let pixel = self.image.get_pixel(i, j).0;
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.filtered(rec).xyz()
+    }
 
-        colour_scale * vector![pixel[0] as f64, pixel[1] as f64, pixel[2] as f64]
+    fn alpha(&self, u: f64, v: f64, p: &Vector3<f64>) -> f64 {
+        if self.image.has_alpha() {
+            self.sample(u, v).w.clamp(0.0, 1.0)
+        } else {
+            luminance(&self.value(u, v, p))
+        }
     }
 }
@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use nalgebra::{vector, Vector4};
+
+use crate::asset::{self, AssetError};
+
+/// The width and height in texels of the square tiles that images are cached in.
+const TILE_SIZE: u32 = 64;
+
+/// Decoded images, shared between every texture that reads the same file in the same colour
+/// space. Images are cached in tiles at each mip level, built as they are first looked up. With a
+/// memory budget, the least recently used tiles are dropped to make room for new ones, and
+/// rebuilt if they are needed again.
+pub struct TextureCache {
+    budget: Option<usize>,
+    fallback: bool,
+    images: Mutex<HashMap<(PathBuf, bool), Arc<CachedImage>>>,
+    resident: AtomicUsize,
+    clock: AtomicU64,
+}
+
+static CACHE: OnceLock<TextureCache> = OnceLock::new();
+
+impl TextureCache {
+    fn with_budget(budget: Option<usize>, fallback: bool) -> Self {
+        Self {
+            budget,
+            fallback,
+            images: Mutex::default(),
+            resident: AtomicUsize::new(0),
+            clock: AtomicU64::new(1),
+        }
+    }
+
+    /// Limits the cache to `budget` bytes, if given, and stands a magenta checker in for images
+    /// that can't be read if `fallback` is set. Must come before any texture loads.
+    pub fn init(budget: Option<usize>, fallback: bool) {
+        assert!(
+            CACHE.set(Self::with_budget(budget, fallback)).is_ok(),
+            "the texture cache is already in use"
+        );
+    }
+
+    pub fn global() -> &'static Self {
+        CACHE.get_or_init(|| Self::with_budget(None, false))
+    }
+
+    /// The image at `path`, decoded from sRGB if `srgb` is set. The file is decoded here, so that
+    /// a corrupt one is reported before rendering starts, though with a budget its tiles may be
+    /// dropped and decoded again later.
+    pub fn image(&'static self, path: PathBuf, srgb: bool) -> Result<Arc<CachedImage>, AssetError> {
+        let mut images = self.images.lock().unwrap();
+        if let Some(image) = images.get(&(path.clone(), srgb)) {
+            return Ok(image.clone());
+        }
+
+        let (decoded, size) = match asset::decode_image(&path) {
+            Ok(decoded) => {
+                let size = (decoded.width(), decoded.height());
+                (Some(decoded), size)
+            }
+            Err(error) if self.fallback => {
+                eprintln!("{error}, so rendering a checker in its place");
+                (None, (TILE_SIZE, TILE_SIZE))
+            }
+            Err(error) => return Err(error),
+        };
+        let bytes = size.0 as usize * size.1 as usize * std::mem::size_of::<[u8; 4]>();
+        if self.budget.is_some_and(|budget| bytes > budget) {
+            eprintln!(
+                "{} doesn't fit in the texture budget, and will be decoded again and again",
+                path.display()
+            );
+        }
+
+        let image = Arc::new(CachedImage::new(
+            self,
+            path.clone(),
+            srgb,
+            size,
+            decoded.is_none(),
+        ));
+        images.insert((path, srgb), image.clone());
+        // Storing the tiles may evict others, which needs the lock.
+        drop(images);
+        if let Some(decoded) = decoded {
+            let _decoding = image.decoding.lock().unwrap();
+            image.store(decoded, None);
+        }
+        Ok(image)
+    }
+
+    /// `image` decoded from sRGB if `srgb` is set.
+    pub fn in_colour_space(&'static self, image: &CachedImage, srgb: bool) -> Arc<CachedImage> {
+        self.images
+            .lock()
+            .unwrap()
+            .entry((image.path.clone(), srgb))
+            .or_insert_with(|| {
+                Arc::new(CachedImage::new(
+                    self,
+                    image.path.clone(),
+                    srgb,
+                    image.size(0),
+                    image.missing,
+                ))
+            })
+            .clone()
+    }
+
+    /// The time for least recently used eviction, which moves on whenever anything is loaded
+    /// rather than on every lookup, to keep lookups from contending.
+    fn now(&self) -> u64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+
+    /// Makes room for `bytes` more, evicting the least recently used tiles if they wouldn't fit
+    /// in the budget.
+    fn reserve(&self, bytes: usize) {
+        self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(budget) = self.budget {
+            if self.resident.load(Ordering::Relaxed) + bytes > budget {
+                // Evict to a little under budget, so that the next few loads don't each need a scan.
+                self.evict_down_to((budget - budget / 10).saturating_sub(bytes));
+            }
+        }
+        self.resident.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Takes `bytes` from what is left of the budget, if there's room without evicting anything.
+    fn try_reserve(&self, bytes: usize) -> bool {
+        let budget = self.budget.unwrap_or(usize::MAX);
+        self.resident
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |resident| {
+                (resident + bytes <= budget).then_some(resident + bytes)
+            })
+            .is_ok()
+    }
+
+    /// Gives back bytes that were reserved for a tile that wasn't stored after all.
+    fn release(&self, bytes: usize) {
+        self.resident.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    fn evict_down_to(&self, target: usize) {
+        let images: Vec<_> = self.images.lock().unwrap().values().cloned().collect();
+        let mut slots: Vec<_> = images.iter().flat_map(|image| image.slots()).collect();
+        slots.sort_by_key(|slot| slot.last_used.load(Ordering::Relaxed));
+        for slot in slots {
+            if self.resident.load(Ordering::Relaxed) <= target {
+                break;
+            }
+            self.release(slot.evict());
+        }
+    }
+}
+
+/// The texels of one tile, in rows. The full resolution image keeps its 8-bit encoding, and the
+/// smaller mip levels, which average it, are linear with 16 bits per channel.
+enum Tile {
+    Encoded(Vec<[u8; 4]>),
+    Linear(Vec<[u16; 4]>),
+}
+
+impl Tile {
+    fn bytes(&self) -> usize {
+        match self {
+            Tile::Encoded(texels) => std::mem::size_of_val(texels.as_slice()),
+            Tile::Linear(texels) => std::mem::size_of_val(texels.as_slice()),
+        }
+    }
+}
+
+pub struct CachedImage {
+    cache: &'static TextureCache,
+    path: PathBuf,
+    srgb: bool,
+    // Whether the file couldn't be read, and a checker stands in for it.
+    missing: bool,
+    // The width and height of each mip level, halving down to a single texel.
+    levels: Vec<(u32, u32)>,
+    // Known once the image has been decoded.
+    has_alpha: OnceLock<bool>,
+    // Tiles at each mip level, in rows.
+    tiles: Vec<Vec<Slot>>,
+    decoding: Mutex<()>,
+}
+
+impl CachedImage {
+    fn new(
+        cache: &'static TextureCache,
+        path: PathBuf,
+        srgb: bool,
+        (width, height): (u32, u32),
+        missing: bool,
+    ) -> Self {
+        let mut levels = vec![(width, height)];
+        while let Some(&(width, height)) = levels.last().filter(|&&size| size != (1, 1)) {
+            levels.push(((width / 2).max(1), (height / 2).max(1)));
+        }
+        let tiles_across = |size: u32| size.div_ceil(TILE_SIZE) as usize;
+        let tiles = levels
+            .iter()
+            .map(|&(width, height)| {
+                (0..tiles_across(width) * tiles_across(height))
+                    .map(|_| Slot::default())
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            cache,
+            path,
+            srgb,
+            missing,
+            levels,
+            has_alpha: OnceLock::new(),
+            tiles,
+            decoding: Mutex::new(()),
+        }
+    }
+
+    /// The width and height of mip level `level`, where level 0 is the full image.
+    pub fn size(&self, level: usize) -> (u32, u32) {
+        self.levels[level]
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        if let Some(&has_alpha) = self.has_alpha.get() {
+            return has_alpha;
+        }
+        self.decode(None);
+        self.has_alpha.get().copied().unwrap()
+    }
+
+    /// The texel at column `i` and row `j` of mip level `level`, in linear RGB with alpha.
+    pub fn texel(&self, level: usize, i: u32, j: u32) -> Vector4<f64> {
+        let (width, _) = self.size(level);
+        let tile = (j / TILE_SIZE) * width.div_ceil(TILE_SIZE) + i / TILE_SIZE;
+        let index = ((j % TILE_SIZE) * TILE_SIZE + i % TILE_SIZE) as usize;
+        let slot = &self.tiles[level][tile as usize];
+        if let Some(texel) = slot.with(self.cache.now(), |tile| self.read(tile, index)) {
+            return texel;
+        }
+        self.read(&self.load(level, tile as usize), index)
+    }
+
+    fn read(&self, tile: &Tile, index: usize) -> Vector4<f64> {
+        match tile {
+            Tile::Encoded(texels) => {
+                let texel = texels[index];
+                let channel = |value: u8| {
+                    if self.srgb {
+                        srgb_to_linear(value)
+                    } else {
+                        value as f64 / 255.0
+                    }
+                };
+                vector![
+                    channel(texel[0]),
+                    channel(texel[1]),
+                    channel(texel[2]),
+                    texel[3] as f64 / 255.0
+                ]
+            }
+            Tile::Linear(texels) => Vector4::from(texels[index]).cast() / u16::MAX as f64,
+        }
+    }
+
+    /// Makes tile `tile` of mip level `level` resident.
+    fn load(&self, level: usize, tile: usize) -> Arc<Tile> {
+        if level == 0 {
+            return self.decode(Some(tile)).unwrap();
+        }
+
+        self.tiles[level][tile].get_or_build(self.cache, || {
+            let (width, height) = self.size(level);
+            let tiles_across = width.div_ceil(TILE_SIZE) as usize;
+            let (tile_i, tile_j) = (tile % tiles_across, tile / tiles_across);
+            Tile::Linear(
+                tile_texels(tile_i as u32, tile_j as u32, width, height)
+                    .map(|(i, j)| {
+                        let texel = self.average_below(level, i, j) * u16::MAX as f64;
+                        texel.map(|c| c.round() as u16).into()
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    /// Reads the image, filling in whether it has alpha and any tiles of the full resolution
+    /// image that aren't resident, and returns tile `wanted` of it. JPEG and PNG can't be decoded
+    /// in part, so this is the only way to load them.
+    fn decode(&self, wanted: Option<usize>) -> Option<Arc<Tile>> {
+        let _decoding = self.decoding.lock().unwrap();
+        // Another thread may have decoded the image while this one waited.
+        match wanted {
+            Some(wanted) => {
+                if let Some(tile) = self.tiles[0][wanted].get() {
+                    return Some(tile);
+                }
+            }
+            None if self.has_alpha.get().is_some() => return None,
+            None => {}
+        }
+
+        let image = if self.missing {
+            missing_checker().into()
+        } else {
+            // The file was decoded when the image was added, so this only fails if it has since
+            // changed.
+            asset::decode_image(&self.path).unwrap_or_else(|error| {
+                eprintln!("{error}, so rendering a checker in its place");
+                missing_checker().into()
+            })
+        };
+        self.store(image, wanted)
+    }
+
+    /// Fills in whether the image has alpha from `image`, and returns tile `wanted` of the full
+    /// resolution image, made resident. Other tiles are only kept while they fit in what is left
+    /// of the budget: all of them when the image is first added, and afterwards just those that
+    /// have been read before. A checker standing in for the image repeats across it.
+    fn store(&self, image: DynamicImage, wanted: Option<usize>) -> Option<Arc<Tile>> {
+        self.has_alpha
+            .get_or_init(|| !self.missing && image.color().has_alpha());
+        let image = image.into_rgba8();
+        let (width, height) = self.size(0);
+        let tiles_across = width.div_ceil(TILE_SIZE) as usize;
+        let tile = |index: usize| {
+            let (tile_i, tile_j) = (index % tiles_across, index / tiles_across);
+            Arc::new(Tile::Encoded(
+                tile_texels(tile_i as u32, tile_j as u32, width, height)
+                    .map(|(i, j)| image.get_pixel(i % image.width(), j % image.height()).0)
+                    .collect(),
+            ))
+        };
+
+        let found = wanted.map(|wanted| {
+            let tile = tile(wanted);
+            let slot = &self.tiles[0][wanted];
+            self.cache.reserve(tile.bytes());
+            if !slot.fill(&tile) {
+                self.cache.release(tile.bytes());
+            }
+            slot.touch(self.cache.now());
+            tile
+        });
+        for (index, slot) in self.tiles[0].iter().enumerate() {
+            let first_added = wanted.is_none();
+            if Some(index) == wanted || slot.is_resident() || !(first_added || slot.was_read()) {
+                continue;
+            }
+            let tile = tile(index);
+            if !self.cache.try_reserve(tile.bytes()) {
+                break;
+            }
+            if !slot.fill(&tile) {
+                self.cache.release(tile.bytes());
+            }
+        }
+        found
+    }
+
+    /// The average of the two by two block of texels under `(i, j)` at the next level up.
+    fn average_below(&self, level: usize, i: u32, j: u32) -> Vector4<f64> {
+        let (width, height) = self.size(level - 1);
+        let texel = |i: u32, j: u32| self.texel(level - 1, i.min(width - 1), j.min(height - 1));
+        (texel(2 * i, 2 * j)
+            + texel(2 * i + 1, 2 * j)
+            + texel(2 * i, 2 * j + 1)
+            + texel(2 * i + 1, 2 * j + 1))
+            / 4.0
+    }
+
+    fn slots(&self) -> impl Iterator<Item = &Slot> {
+        self.tiles.iter().flatten()
+    }
+}
+
+/// The texels of the tile at column `tile_i` and row `tile_j`, in rows. Tiles that overhang the
+/// edge of the image repeat its last texel.
+fn tile_texels(
+    tile_i: u32,
+    tile_j: u32,
+    width: u32,
+    height: u32,
+) -> impl Iterator<Item = (u32, u32)> {
+    (0..TILE_SIZE).flat_map(move |j| {
+        (0..TILE_SIZE).map(move |i| {
+            (
+                (tile_i * TILE_SIZE + i).min(width - 1),
+                (tile_j * TILE_SIZE + j).min(height - 1),
+            )
+        })
+    })
+}
+
+/// Magenta and black squares, eight across, standing in for an image that couldn't be read.
+fn missing_checker() -> RgbaImage {
+    RgbaImage::from_fn(TILE_SIZE, TILE_SIZE, |i, j| {
+        if (i / 8 + j / 8) % 2 == 0 {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+/// Decodes an 8-bit sRGB value to linear, through a table built on first use.
+fn srgb_to_linear(value: u8) -> f64 {
+    static TABLE: OnceLock<[f64; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|i| {
+            let c = i as f64 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    })[value as usize]
+}
+
+/// A tile that may or may not be resident.
+#[derive(Default)]
+struct Slot {
+    tile: RwLock<Option<Arc<Tile>>>,
+    last_used: AtomicU64,
+}
+
+impl Slot {
+    fn touch(&self, now: u64) {
+        // Only write when the time has moved on, so lookups of the same tile don't contend.
+        if self.last_used.load(Ordering::Relaxed) < now {
+            self.last_used.store(now, Ordering::Relaxed);
+        }
+    }
+
+    fn was_read(&self) -> bool {
+        self.last_used.load(Ordering::Relaxed) > 0
+    }
+
+    fn is_resident(&self) -> bool {
+        self.tile.read().unwrap().is_some()
+    }
+
+    fn get(&self) -> Option<Arc<Tile>> {
+        self.tile.read().unwrap().clone()
+    }
+
+    /// Reads the tile in place, if it is resident.
+    fn with<R>(&self, now: u64, f: impl FnOnce(&Tile) -> R) -> Option<R> {
+        self.touch(now);
+        self.tile.read().unwrap().as_deref().map(f)
+    }
+
+    fn get_or_build(&self, cache: &TextureCache, build: impl FnOnce() -> Tile) -> Arc<Tile> {
+        let mut slot = self.tile.write().unwrap();
+        if let Some(tile) = slot.as_ref() {
+            return tile.clone();
+        }
+        let tile = Arc::new(build());
+        cache.reserve(tile.bytes());
+        *slot = Some(tile.clone());
+        drop(slot);
+
+        self.touch(cache.now());
+        tile
+    }
+
+    /// Stores `tile` if none is resident, returning whether it did. Skips a slot that is busy,
+    /// rather than wait on it.
+    fn fill(&self, tile: &Arc<Tile>) -> bool {
+        match self.tile.try_write() {
+            Ok(mut slot) if slot.is_none() => {
+                *slot = Some(tile.clone());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops the tile, if it is resident and not busy, returning the bytes freed.
+    fn evict(&self) -> usize {
+        match self.tile.try_write() {
+            Ok(mut slot) => slot.take().map_or(0, |tile| tile.bytes()),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use image::{Rgba, RgbaImage};
+    use nalgebra::vector;
+
+    use super::{TextureCache, TILE_SIZE};
+
+    #[test]
+    fn eviction_keeps_within_budget() {
+        let path = std::env::temp_dir().join(format!("texture-cache-{}.png", std::process::id()));
+        RgbaImage::from_fn(256, 256, |i, j| Rgba([i as u8, j as u8, 0, 255]))
+            .save(&path)
+            .unwrap();
+        // Room for three of the sixteen tiles.
+        let budget = 3 * (TILE_SIZE * TILE_SIZE) as usize * 4;
+        let cache = Box::leak(Box::new(TextureCache::with_budget(Some(budget), false)));
+        let image = cache.image(path.clone(), false).unwrap();
+
+        for pass in 0..2 {
+            for j in (0..256).step_by(32) {
+                for i in (0..256).step_by(32) {
+                    let expected = vector![i as f64, j as f64, 0.0, 255.0] / 255.0;
+                    assert_eq!(image.texel(0, i, j), expected, "pass {pass}");
+                    assert!(cache.resident.load(Ordering::Relaxed) <= budget);
+                }
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+use nalgebra::{vector, Point3, Similarity3, Unit, UnitQuaternion, Vector3};
+
+use crate::asset::{self, AssetError};
+use crate::hittable::HitRecord;
+use crate::perlin::Fractal;
+use crate::procedural::{
+    ColourRamp, FractalKind, FractalNoise, Marble, Voronoi, VoronoiFeature, Warped, Wood,
+};
+use crate::texture::{
+    luminance, CheckerPattern, CheckerTexture, ImageTexture, IntoTexture, NoiseTexture, Texture,
+};
+
+/// Blends from `a` to `b` by `mask`, channel by channel, so a mask of 0 gives `a` and 1 gives `b`.
+pub struct Mix {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    mask: Arc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: impl IntoTexture, b: impl IntoTexture, mask: impl IntoTexture) -> Self {
+        Self {
+            a: a.into_texture(),
+            b: b.into_texture(),
+            mask: mask.into_texture(),
+        }
+    }
+}
+
+fn mix(a: Vector3<f64>, b: Vector3<f64>, mask: Vector3<f64>) -> Vector3<f64> {
+    a.component_mul(&mask.map(|m| 1.0 - m)) + b.component_mul(&mask)
+}
+
+impl Texture for Mix {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        mix(
+            self.a.value(u, v, p),
+            self.b.value(u, v, p),
+            self.mask.value(u, v, p),
+        )
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        mix(
+            self.a.value_at(rec),
+            self.b.value_at(rec),
+            self.mask.value_at(rec),
+        )
+    }
+}
+
+pub struct Add {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+}
+
+impl Add {
+    pub fn new(a: impl IntoTexture, b: impl IntoTexture) -> Self {
+        Self {
+            a: a.into_texture(),
+            b: b.into_texture(),
+        }
+    }
+}
+
+impl Texture for Add {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.a.value(u, v, p) + self.b.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.a.value_at(rec) + self.b.value_at(rec)
+    }
+}
+
+/// Multiplies two textures channel by channel. Multiplying by a constant scales a texture.
+pub struct Multiply {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+}
+
+impl Multiply {
+    pub fn new(a: impl IntoTexture, b: impl IntoTexture) -> Self {
+        Self {
+            a: a.into_texture(),
+            b: b.into_texture(),
+        }
+    }
+}
+
+impl Texture for Multiply {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.a.value(u, v, p).component_mul(&self.b.value(u, v, p))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.a.value_at(rec).component_mul(&self.b.value_at(rec))
+    }
+}
+
+/// Maps the luminance of another texture through a colour ramp.
+pub struct Ramp {
+    texture: Arc<dyn Texture>,
+    ramp: ColourRamp,
+}
+
+impl Ramp {
+    pub fn new(texture: Arc<dyn Texture>, ramp: ColourRamp) -> Self {
+        Self { texture, ramp }
+    }
+}
+
+impl Texture for Ramp {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.ramp.value(luminance(&self.texture.value(u, v, p)))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.ramp.value(luminance(&self.texture.value_at(rec)))
+    }
+}
+
+/// Linearly maps each channel of another texture from `from` to `to`, clamping to `to`. Useful
+/// for sharpening or softening a mask.
+pub struct Remap {
+    texture: Arc<dyn Texture>,
+    from: (f64, f64),
+    to: (f64, f64),
+}
+
+impl Remap {
+    pub fn new(texture: Arc<dyn Texture>, from: (f64, f64), to: (f64, f64)) -> Self {
+        Self { texture, from, to }
+    }
+
+    fn remap(&self, value: Vector3<f64>) -> Vector3<f64> {
+        value.map(|c| {
+            let t = ((c - self.from.0) / (self.from.1 - self.from.0)).clamp(0.0, 1.0);
+            self.to.0 + t * (self.to.1 - self.to.0)
+        })
+    }
+}
+
+impl Texture for Remap {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.remap(self.texture.value(u, v, p))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.remap(self.texture.value_at(rec))
+    }
+}
+
+/// Projects a uv texture onto a surface along each axis and blends the three by how squarely the
+/// surface faces them, for surfaces without usable uv coordinates. Lookups without a hit record
+/// to give the normal blend the three equally.
+pub struct Triplanar {
+    texture: Arc<dyn Texture>,
+    scale: f64,
+    sharpness: f64,
+}
+
+impl Triplanar {
+    /// `scale` is the number of times the texture repeats per unit length.
+    pub fn new(texture: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            texture,
+            scale,
+            sharpness: 4.0,
+        }
+    }
+
+    /// How quickly the projections give way to each other where the surface turns, with higher
+    /// values giving narrower blends.
+    pub fn with_sharpness(mut self, sharpness: f64) -> Self {
+        self.sharpness = sharpness;
+        self
+    }
+
+    /// The texture coordinates of `p` along each axis, with the rates of change of the point with
+    /// them, paired with how much of each projection to take from `weights`.
+    fn projections(
+        &self,
+        p: &Vector3<f64>,
+        weights: Vector3<f64>,
+    ) -> impl Iterator<Item = (f64, f64, f64, Vector3<f64>, Vector3<f64>)> {
+        let q = self.scale * p;
+        let weights = weights / weights.sum();
+        let step = 1.0 / self.scale;
+        let (x, y, z) = (
+            vector![step, 0.0, 0.0],
+            vector![0.0, step, 0.0],
+            vector![0.0, 0.0, step],
+        );
+        [
+            (weights.x, q.z, q.y, z, y),
+            (weights.y, q.x, q.z, x, z),
+            (weights.z, q.x, q.y, x, y),
+        ]
+        .into_iter()
+        .filter(|&(weight, ..)| weight > 0.0)
+    }
+}
+
+impl Texture for Triplanar {
+    fn value(&self, _u: f64, _v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.projections(p, vector![1.0, 1.0, 1.0])
+            .map(|(weight, u, v, _, _)| self.texture.value(u, v, p) * weight)
+            .sum()
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        let weights = rec.normal().map(|n| n.abs().powf(self.sharpness));
+        self.projections(&rec.point(), weights)
+            .map(|(weight, u, v, dpdu, dpdv)| {
+                let projected = rec.clone().with_uv(u, v).with_tangents(dpdu, dpdv);
+                self.texture.value_at(&projected) * weight
+            })
+            .sum()
+    }
+}
+
+/// Moves, turns and scales another texture through space.
+pub struct Transformed {
+    texture: Arc<dyn Texture>,
+    transform: Similarity3<f64>,
+}
+
+impl Transformed {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        Self {
+            texture,
+            transform: Similarity3::identity(),
+        }
+    }
+
+    /// Scales the pattern up by `scale`, after any earlier transforms.
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.transform.append_scaling_mut(scale);
+        self
+    }
+
+    /// Turns the pattern anticlockwise by `degrees` about `axis`, after any earlier transforms.
+    pub fn with_rotation(mut self, axis: Vector3<f64>, degrees: f64) -> Self {
+        let rotation =
+            UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), degrees.to_radians());
+        self.transform.append_rotation_mut(&rotation);
+        self
+    }
+
+    /// Moves the pattern by `offset`, after any earlier transforms.
+    pub fn with_translation(mut self, offset: Vector3<f64>) -> Self {
+        self.transform.append_translation_mut(&offset.into());
+        self
+    }
+
+    fn to_texture(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        self.transform
+            .inverse_transform_point(&Point3::from(*p))
+            .coords
+    }
+}
+
+impl Texture for Transformed {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.texture.value(u, v, &self.to_texture(p))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        let moved = rec.clone().transformed(
+            |p| self.to_texture(p),
+            |d| self.transform.inverse_transform_vector(d),
+        );
+        self.texture.value_at(&moved)
+    }
+}
+
+/// Looks up another texture at points relative to the object that was hit, before any
+/// `Translate` or `RotateY` placed it in the world, so that solid textures move with it. Lookups
+/// without a hit record have no object, and use world space.
+pub struct ObjectSpace {
+    texture: Arc<dyn Texture>,
+}
+
+impl ObjectSpace {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        Self { texture }
+    }
+}
+
+impl Texture for ObjectSpace {
+    fn value(&self, u: f64, v: f64, p: &Vector3<f64>) -> Vector3<f64> {
+        self.texture.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.texture.value_at(&rec.in_object_space())
+    }
+}
+
+/// Textures read from a file, so that they can be built without writing Rust. Each line names a
+/// node, and gives its kind and arguments, which are numbers, colours written as one grey or
+/// three comma separated channels such as `0.8,0.1,0.1`, and the names of nodes on earlier
+/// lines. Colours stand in for textures. Anything after a `#` is a comment.
+///
+/// ```text
+/// # Rust patches with hard edges, from noise sharpened into a mask.
+/// clouds = fbm 2
+/// patches = remap clouds 0.45 0.55 0 1
+/// rust = mix 0.6,0.62,0.65 0.45,0.2,0.08 patches
+/// ```
+///
+/// The sources are `colour c`, `noise scale`, `fbm scale [octaves]`, `ridged scale [octaves]`,
+/// `voronoi distance|edges|cells scale`, `marble scale`, `wood rings` and `image path`, with the
+/// procedural ones in grey for a `ramp` to colour. They combine with `mix a b mask`, `add a b`,
+/// `multiply a b`, `ramp t position colour...`, `remap t from0 from1 to0 to1`,
+/// `checker even odd [cell_size]`, `triplanar t scale [sharpness]`, `warp t scale strength`,
+/// `scale t factor`, `rotate t axis degrees`, `translate t offset` and `object t`.
+pub struct TextureGraph {
+    path: PathBuf,
+    textures: HashMap<String, Arc<dyn Texture>>,
+}
+
+impl TextureGraph {
+    pub fn new(path: PathBuf) -> Result<Self, AssetError> {
+        let contents = asset::read_to_string(&path)?;
+        Self::parse(path, &contents)
+    }
+
+    fn parse(path: PathBuf, contents: &str) -> Result<Self, AssetError> {
+        let mut textures = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, node) = line.split_once('=').ok_or_else(|| {
+                line_error(&path, index + 1, "expected `name = kind arguments...`")
+            })?;
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(line_error(
+                    &path,
+                    index + 1,
+                    format!("{name:?} isn't a name"),
+                ));
+            }
+
+            let mut args = Args {
+                tokens: node.split_whitespace().peekable(),
+                textures: &textures,
+                path: &path,
+                line: index + 1,
+            };
+            let texture = build(&mut args)?;
+            if let Some(extra) = args.tokens.next() {
+                return Err(args.error(format!("{extra:?} is one argument too many")));
+            }
+            textures.insert(name.to_string(), texture);
+        }
+        Ok(Self { path, textures })
+    }
+
+    /// The texture on the line named `name`.
+    pub fn texture(&self, name: &str) -> Result<Arc<dyn Texture>, AssetError> {
+        self.textures.get(name).cloned().ok_or_else(|| {
+            AssetError::parse(&self.path, format!("there's no texture named {name:?}"))
+        })
+    }
+}
+
+/// The arguments of the node on one line of a texture graph file.
+struct Args<'a> {
+    tokens: Peekable<SplitWhitespace<'a>>,
+    textures: &'a HashMap<String, Arc<dyn Texture>>,
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> Args<'a> {
+    fn error(&self, reason: impl Into<String>) -> AssetError {
+        line_error(self.path, self.line, reason)
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, AssetError> {
+        self.tokens
+            .next()
+            .ok_or_else(|| self.error(format!("expected {what}")))
+    }
+
+    fn number(&mut self) -> Result<f64, AssetError> {
+        let token = self.word("a number")?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("{token:?} isn't a number")))
+    }
+
+    /// A number if there are arguments left, or `default`.
+    fn optional_number(&mut self, default: f64) -> Result<f64, AssetError> {
+        match self.tokens.peek() {
+            Some(_) => self.number(),
+            None => Ok(default),
+        }
+    }
+
+    fn colour(&mut self) -> Result<Vector3<f64>, AssetError> {
+        let token = self.word("a colour")?;
+        parse_colour(token).ok_or_else(|| self.error(format!("{token:?} isn't a colour")))
+    }
+
+    fn texture(&mut self) -> Result<Arc<dyn Texture>, AssetError> {
+        let token = self.word("a texture")?;
+        if let Some(texture) = self.textures.get(token) {
+            return Ok(texture.clone());
+        }
+        parse_colour(token)
+            .map(IntoTexture::into_texture)
+            .ok_or_else(|| {
+                self.error(format!(
+                    "{token:?} isn't a colour or a texture named on an earlier line"
+                ))
+            })
+    }
+
+    /// An optional number of octaves, which must be whole and at least one, seven if not given.
+    fn fractal(&mut self) -> Result<Fractal, AssetError> {
+        let octaves = self.optional_number(7.0)?;
+        if octaves < 1.0 || octaves.fract() != 0.0 {
+            return Err(self.error(format!("{octaves} isn't a number of octaves")));
+        }
+        Ok(Fractal::new(octaves as usize, 2.0, 0.5))
+    }
+}
+
+fn line_error(path: &Path, line: usize, reason: impl Into<String>) -> AssetError {
+    AssetError::parse(path, format!("line {line}: {}", reason.into()))
+}
+
+/// One number for a grey, or three separated by commas.
+fn parse_colour(token: &str) -> Option<Vector3<f64>> {
+    let channels = token
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<f64>, _>>()
+        .ok()?;
+    match channels[..] {
+        [grey] => Some(vector![grey, grey, grey]),
+        [r, g, b] => Some(vector![r, g, b]),
+        _ => None,
+    }
+}
+
+/// Black to white, for procedural sources to leave the colouring to a `ramp` node.
+fn grey() -> ColourRamp {
+    ColourRamp::new(&[(0.0, vector![0.0, 0.0, 0.0]), (1.0, vector![1.0, 1.0, 1.0])])
+}
+
+fn build(args: &mut Args) -> Result<Arc<dyn Texture>, AssetError> {
+    let kind = args.word("the kind of node")?;
+    let texture: Arc<dyn Texture> = match kind {
+        "colour" => args.colour()?.into_texture(),
+        "noise" => Arc::new(NoiseTexture::new(args.number()?)),
+        "fbm" | "ridged" => {
+            let kind = if kind == "fbm" {
+                FractalKind::Fbm
+            } else {
+                FractalKind::Ridged
+            };
+            let scale = args.number()?;
+            Arc::new(FractalNoise::new(kind, scale, grey()).with_fractal(args.fractal()?))
+        }
+        "voronoi" => {
+            let feature = match args.word("a feature")? {
+                "distance" => VoronoiFeature::Distance,
+                "edges" => VoronoiFeature::Edges,
+                "cells" => VoronoiFeature::Cells,
+                feature => return Err(args.error(format!("{feature:?} isn't a feature"))),
+            };
+            Arc::new(Voronoi::new(feature, args.number()?, grey()))
+        }
+        "marble" => Arc::new(Marble::new(args.number()?, grey())),
+        "wood" => Arc::new(Wood::new(args.number()?, grey())),
+        "image" => Arc::new(ImageTexture::new(PathBuf::from(args.word("a path")?))?),
+        "mix" => Arc::new(Mix::new(args.texture()?, args.texture()?, args.texture()?)),
+        "add" => Arc::new(Add::new(args.texture()?, args.texture()?)),
+        "multiply" => Arc::new(Multiply::new(args.texture()?, args.texture()?)),
+        "ramp" => {
+            let texture = args.texture()?;
+            let mut stops = Vec::new();
+            while args.tokens.peek().is_some() {
+                stops.push((args.number()?, args.colour()?));
+            }
+            if stops.is_empty() {
+                return Err(args.error("a ramp needs at least one stop"));
+            }
+            Arc::new(Ramp::new(texture, ColourRamp::new(&stops)))
+        }
+        "remap" => {
+            let texture = args.texture()?;
+            let from = (args.number()?, args.number()?);
+            let to = (args.number()?, args.number()?);
+            if from.0 == from.1 {
+                return Err(args.error("a remap needs two different values to map from"));
+            }
+            Arc::new(Remap::new(texture, from, to))
+        }
+        "checker" => {
+            let checker = CheckerTexture::new_from_textures(args.texture()?, args.texture()?);
+            match args.tokens.peek() {
+                Some(_) => Arc::new(checker.with_pattern(CheckerPattern::Solid {
+                    cell_size: args.number()?,
+                })),
+                None => Arc::new(checker),
+            }
+        }
+        "triplanar" => {
+            let texture = args.texture()?;
+            let triplanar = Triplanar::new(texture, args.number()?);
+            let sharpness = args.optional_number(triplanar.sharpness)?;
+            Arc::new(triplanar.with_sharpness(sharpness))
+        }
+        "warp" => Arc::new(Warped::new(args.texture()?, args.number()?, args.number()?)),
+        "scale" => {
+            let texture = args.texture()?;
+            let factor = args.number()?;
+            if factor == 0.0 || !factor.is_finite() {
+                return Err(args.error(format!("can't scale by {factor}")));
+            }
+            Arc::new(Transformed::new(texture).with_scale(factor))
+        }
+        "rotate" => {
+            let texture = args.texture()?;
+            let axis = args.colour()?;
+            if axis.norm() == 0.0 {
+                return Err(args.error("an axis can't be zero"));
+            }
+            Arc::new(Transformed::new(texture).with_rotation(axis, args.number()?))
+        }
+        "translate" => Arc::new(Transformed::new(args.texture()?).with_translation(args.colour()?)),
+        "object" => Arc::new(ObjectSpace::new(args.texture()?)),
+        _ => return Err(args.error(format!("{kind:?} isn't a kind of node"))),
+    };
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use nalgebra::vector;
+
+    use super::TextureGraph;
+
+    fn parse(contents: &str) -> Result<TextureGraph, String> {
+        TextureGraph::parse(PathBuf::from("test.tex"), contents).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn nodes_build_on_earlier_lines() {
+        let graph = parse(
+            "# A comment\n\
+             half = colour 0.5\n\
+             tinted = multiply half 0.2,0.4,1   # and another\n\
+             shifted = add tinted 0.1\n",
+        )
+        .unwrap();
+        let p = vector![0.0, 0.0, 0.0];
+        let value = graph.texture("shifted").unwrap().value(0.0, 0.0, &p);
+        assert!((value - vector![0.2, 0.3, 0.6]).norm() < 1e-12);
+        assert!(graph.texture("missing").is_err());
+    }
+
+    #[test]
+    fn bad_nodes_are_errors_with_their_line() {
+        for (contents, reason) in [
+            ("a = ramp 0.5", "line 1: a ramp needs at least one stop"),
+            ("a = fbm 2 0", "line 1: 0 isn't a number of octaves"),
+            (
+                "a = colour 1\nb = mix a c a",
+                "line 2: \"c\" isn't a colour",
+            ),
+            ("a = noise", "line 1: expected a number"),
+            ("a = noise 1 2", "line 1: \"2\" is one argument too many"),
+            ("a = colour 1\nb = scale a 0", "line 2: can't scale by 0"),
+            (
+                "a = colour 1\nb = scale a inf",
+                "line 2: can't scale by inf",
+            ),
+            ("a = sparkle", "line 1: \"sparkle\" isn't a kind of node"),
+            ("noise 1", "line 1: expected `name = kind arguments...`"),
+        ] {
+            let error = parse(contents).err().unwrap();
+            assert!(error.contains(reason), "{error:?} doesn't say {reason:?}");
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use nalgebra::{vector, Vector2, Vector3};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::random::random_double;
+use crate::ray::Ray;
+
+pub struct Triangle {
+    vertices: [Vector3<f64>; 3],
+    uvs: [Vector2<f64>; 3],
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    /// A triangle facing the side from which its vertices wind anticlockwise.
+    pub fn new(
+        v0: Vector3<f64>,
+        v1: Vector3<f64>,
+        v2: Vector3<f64>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            vertices: [v0, v1, v2],
+            uvs: [vector![0.0, 0.0], vector![1.0, 0.0], vector![0.0, 1.0]],
+            material,
+        }
+    }
+
+    /// Gives each vertex texture coordinates, in place of `(0, 0)`, `(1, 0)` and `(0, 1)`.
+    pub fn with_uvs(mut self, uv0: Vector2<f64>, uv1: Vector2<f64>, uv2: Vector2<f64>) -> Self {
+        self.uvs = [uv0, uv1, uv2];
+        self
+    }
+
+    fn normal(&self) -> Vector3<f64> {
+        let [v0, v1, v2] = self.vertices;
+        (v1 - v0).cross(&(v2 - v0)).normalize()
+    }
+
+    /// The rates of change of the point with u and v, from how they change along the edges. Any
+    /// pair of directions in the plane will do if the texture coordinates are degenerate.
+    fn tangents(&self) -> (Vector3<f64>, Vector3<f64>) {
+        let [v0, v1, v2] = self.vertices;
+        let [uv0, uv1, uv2] = self.uvs;
+        let (e1, e2) = (v1 - v0, v2 - v0);
+        let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+
+        let determinant = duv1.x * duv2.y - duv1.y * duv2.x;
+        if determinant.abs() < 1e-12 {
+            let normal = self.normal();
+            let dpdu = e1.normalize();
+            return (dpdu, normal.cross(&dpdu));
+        }
+        (
+            (duv2.y * e1 - duv1.y * e2) / determinant,
+            (duv1.x * e2 - duv2.x * e1) / determinant,
+        )
+    }
+
+    fn record(&self, b1: f64, b2: f64, t: f64, r: &Ray) -> HitRecord {
+        let [v0, v1, v2] = self.vertices;
+        let [uv0, uv1, uv2] = self.uvs;
+        let b0 = 1.0 - b1 - b2;
+        let uv = b0 * uv0 + b1 * uv1 + b2 * uv2;
+        let (dpdu, dpdv) = self.tangents();
+        HitRecord::new(
+            b0 * v0 + b1 * v1 + b2 * v2,
+            self.normal(),
+            self.material.clone(),
+            t,
+            uv.x,
+            uv.y,
+            r,
+        )
+        .with_tangents(dpdu, dpdv)
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Möller and Trumbore's intersection, solving for t and two barycentric coordinates.
+        let [v0, v1, v2] = self.vertices;
+        let (e1, e2) = (v1 - v0, v2 - v0);
+        let pvec = r.direction.cross(&e2);
+        let determinant = e1.dot(&pvec);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        let tvec = r.origin - v0;
+        let b1 = tvec.dot(&pvec) / determinant;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&e1);
+        let b2 = r.direction.dot(&qvec) / determinant;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qvec) / determinant;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        Some(self.record(b1, b2, t, r))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        // Pad each side a little, so that a triangle lying in an axis plane has some thickness.
+        let [v0, v1, v2] = self.vertices;
+        let padding = vector![0.0001, 0.0001, 0.0001];
+        Some(Aabb::new(
+            v0.inf(&v1).inf(&v2) - padding,
+            v0.sup(&v1).sup(&v2) + padding,
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        let [v0, v1, v2] = self.vertices;
+        0.5 * (v1 - v0).cross(&(v2 - v0)).norm()
+    }
+
+    fn sample_surface(&self, _time: f64) -> Option<HitRecord> {
+        // Fold the unit square onto the triangle for uniform barycentric coordinates.
+        let (mut b1, mut b2) = (random_double(), random_double());
+        if b1 + b2 > 1.0 {
+            (b1, b2) = (1.0 - b1, 1.0 - b2);
+        }
+        let [v0, v1, v2] = self.vertices;
+        let uv = (1.0 - b1 - b2) * self.uvs[0] + b1 * self.uvs[1] + b2 * self.uvs[2];
+        Some(HitRecord::on_surface(
+            (1.0 - b1 - b2) * v0 + b1 * v1 + b2 * v2,
+            self.normal(),
+            self.material.clone(),
+            uv.x,
+            uv.y,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nalgebra::vector;
+
+    use super::Triangle;
+    use crate::hittable::Hittable;
+    use crate::material::Lambertian;
+    use crate::ray::Ray;
+
+    #[test]
+    fn tangents_follow_the_texture_coordinates() {
+        let material = Arc::new(Lambertian::new(vector![0.5, 0.5, 0.5]));
+        let (v0, v1, v2) = (
+            vector![0.0, 0.0, 0.0],
+            vector![2.0, 0.0, 0.0],
+            vector![0.0, 0.0, -4.0],
+        );
+        // u runs along the second edge and v back along the first.
+        let triangle = Triangle::new(v0, v1, v2, material).with_uvs(
+            vector![0.0, 1.0],
+            vector![0.0, 0.0],
+            vector![1.0, 1.0],
+        );
+
+        let r = Ray::new(vector![0.5, 1.0, -0.5], vector![0.0, -1.0, 0.0], 0.0);
+        let rec = triangle.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.normal(), vector![0.0, 1.0, 0.0]);
+        assert!((rec.dpdu() - vector![0.0, 0.0, -4.0]).norm() < 1e-12);
+        assert!((rec.dpdv() - vector![-2.0, 0.0, 0.0]).norm() < 1e-12);
+        assert!((rec.u() - 0.125).abs() < 1e-12 && (rec.v() - 0.75).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,63 @@
+use nalgebra::{vector, Vector3};
+
+use crate::random::{hash_point, random_range_vector3};
+
+/// Cellular noise, from one randomly placed feature point in each unit cube of space.
+pub struct Worley {
+    // Decorrelates separate instances, which would otherwise share their feature points.
+    offset: Vector3<f64>,
+}
+
+/// The distances from a point to its nearest and second nearest feature points, and a number in
+/// `[0, 1)` that identifies the nearest one's cell.
+pub struct WorleySample {
+    pub f1: f64,
+    pub f2: f64,
+    pub cell: f64,
+}
+
+impl Worley {
+    pub fn new() -> Self {
+        Self {
+            offset: random_range_vector3(-1000.0, 1000.0),
+        }
+    }
+
+    pub fn sample(&self, p: &Vector3<f64>) -> WorleySample {
+        let p = p + self.offset;
+        let base = p.map(f64::floor);
+        let mut sample = WorleySample {
+            f1: f64::INFINITY,
+            f2: f64::INFINITY,
+            cell: 0.0,
+        };
+
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let cell = base + vector![i as f64, j as f64, k as f64];
+                    let distance = (cell + feature_point(&cell) - p).norm();
+                    if distance < sample.f1 {
+                        sample.f2 = sample.f1;
+                        sample.f1 = distance;
+                        sample.cell = hash_point(&(cell + vector![0.0, 0.0, 0.5]));
+                    } else if distance < sample.f2 {
+                        sample.f2 = distance;
+                    }
+                }
+            }
+        }
+
+        sample
+    }
+}
+
+/// The feature point of the cell with corner `cell`, relative to that corner. Hashing points
+/// halfway along the cell's edges keeps the coordinates independent of each other.
+fn feature_point(cell: &Vector3<f64>) -> Vector3<f64> {
+    vector![
+        hash_point(cell),
+        hash_point(&(cell + vector![0.5, 0.0, 0.0])),
+        hash_point(&(cell + vector![0.0, 0.5, 0.0]))
+    ]
+}